@@ -2,9 +2,19 @@
 
 in layout(location=0) vec4 inColor;
 in layout(location=1) vec3 inNormal;
+in layout(location=2) vec2 inTexCoord;
+in layout(location=3) vec4 inTangent; // Handedness in w, for normal maps
+layout(location=5) uniform vec4 inBaseColor;
+layout(location=6) uniform bool inUseTexture;
+layout(binding=0) uniform sampler2D baseColorTexture;
 out vec4 color;
 
 void main() {
+    vec4 albedo = inColor * inBaseColor;
+    if (inUseTexture) {
+        albedo *= texture(baseColorTexture, inTexCoord);
+    }
+
     vec3 lightDirection = normalize(vec3(0.8, -0.5, 0.6));
-    color = vec4(inColor.rgb * max(0, dot(inNormal, -lightDirection)), inColor.a);
+    color = vec4(albedo.rgb * max(0, dot(inNormal, -lightDirection)), albedo.a);
 }
\ No newline at end of file
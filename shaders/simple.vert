@@ -3,13 +3,19 @@
 in layout(location=0) vec3 inPosition;
 in layout(location=1) vec4 inColor;
 in layout(location=2) vec3 inNormal;
+in layout(location=3) vec4 inTangent;
+in layout(location=4) vec2 inTexCoord;
 layout(location=3) uniform mat4 inTotalTransform; 
 layout(location=4) uniform mat4 inModelTransform;
 out layout(location=0) vec4 outColor;
 out layout(location=1) vec3 outNormal;
+out layout(location=2) vec2 outTexCoord;
+out layout(location=3) vec4 outTangent;
 
 void main() {
     gl_Position = inTotalTransform * vec4(inPosition, 1.0);
     outColor = inColor;
     outNormal = normalize(mat3(inModelTransform) * inNormal);
+    outTexCoord = inTexCoord;
+    outTangent = vec4(mat3(inModelTransform) * inTangent.xyz, inTangent.w);
 }
\ No newline at end of file
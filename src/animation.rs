@@ -0,0 +1,269 @@
+use crate::scene_graph::SceneNode;
+
+/// How values are blended between keyframes
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation {
+    Step, // Hold each value until the next keyframe
+    Linear,
+    Cubic, // Smooth curve through every keyframe
+}
+
+/// What happens once the time goes past the last keyframe
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Repeat {
+    Once,     // Hold the last value
+    Loop,     // Start over from the beginning
+    PingPong, // Play backwards to the beginning, then forwards again
+}
+
+/// Which transform property of a node a track animates
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Target {
+    Position,
+    Rotation,
+    Scale,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Keyframe {
+    pub time: f32,
+    pub value: glm::Vec3,
+}
+
+/// Values of one property over time
+#[derive(Clone, Debug)]
+pub struct Track {
+    pub target: Target,
+    pub interpolation: Interpolation,
+    pub keyframes: Vec<Keyframe>, // Sorted by time
+}
+
+impl Track {
+    pub fn new(target: Target, interpolation: Interpolation) -> Self {
+        Track {
+            target,
+            interpolation,
+            keyframes: Vec::new(),
+        }
+    }
+
+    /// Adds a keyframe, keeping the keyframes sorted by time
+    pub fn key(mut self, time: f32, value: glm::Vec3) -> Self {
+        let index = self.keyframes.iter().take_while(|k| k.time <= time).count();
+        self.keyframes.insert(index, Keyframe { time, value });
+        self
+    }
+
+    /// Time of the last keyframe
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |k| k.time)
+    }
+
+    /// Slope of the curve at the given keyframe, for cubic interpolation. The ends
+    /// use the slope towards their only neighbour
+    fn tangent(&self, i: usize) -> glm::Vec3 {
+        let keys = &self.keyframes;
+        let (before, after) = (i.saturating_sub(1), (i + 1).min(keys.len() - 1));
+        let span = keys[after].time - keys[before].time;
+        if span > 0.0 {
+            (keys[after].value - keys[before].value) / span
+        } else {
+            glm::zero()
+        }
+    }
+
+    /// Value at the given time, or None if there are no keyframes. Times outside the
+    /// track hold the first or last value
+    pub fn sample(&self, time: f32) -> Option<glm::Vec3> {
+        let first = self.keyframes.first()?;
+        let last = self.keyframes.last()?;
+        let i = match self.keyframes.iter().position(|k| k.time > time) {
+            None => return Some(last.value),
+            Some(0) => return Some(first.value),
+            Some(next) => next - 1,
+        };
+        let (from, to) = (&self.keyframes[i], &self.keyframes[i + 1]);
+        let span = to.time - from.time;
+        let t = (time - from.time) / span;
+
+        Some(match self.interpolation {
+            Interpolation::Step => from.value,
+            Interpolation::Linear => from.value + (to.value - from.value) * t,
+            Interpolation::Cubic => {
+                // Cubic Hermite, with tangents that take uneven keyframe spacing into
+                // account (like Catmull-Rom for evenly spaced keyframes)
+                let (t2, t3) = (t * t, t * t * t);
+                from.value * (2.0 * t3 - 3.0 * t2 + 1.0)
+                    + self.tangent(i) * ((t3 - 2.0 * t2 + t) * span)
+                    + to.value * (-2.0 * t3 + 3.0 * t2)
+                    + self.tangent(i + 1) * ((t3 - t2) * span)
+            }
+        })
+    }
+}
+
+/// Tracks that play together, starting at time 0
+#[derive(Clone, Debug)]
+pub struct Animation {
+    pub tracks: Vec<Track>,
+    pub repeat: Repeat,
+}
+
+impl Animation {
+    pub fn new(repeat: Repeat) -> Self {
+        Animation {
+            tracks: Vec::new(),
+            repeat,
+        }
+    }
+
+    pub fn with_track(mut self, track: Track) -> Self {
+        self.tracks.push(track);
+        self
+    }
+
+    /// Time of the last keyframe of any track
+    pub fn duration(&self) -> f32 {
+        self.tracks.iter().map(Track::duration).fold(0.0, f32::max)
+    }
+
+    /// Maps time since the animation started onto its timeline, repeating as needed
+    pub fn local_time(&self, time: f32) -> f32 {
+        let duration = self.duration();
+        if duration <= 0.0 {
+            return 0.0;
+        }
+        match self.repeat {
+            Repeat::Once => time.max(0.0).min(duration),
+            Repeat::Loop => time.rem_euclid(duration),
+            Repeat::PingPong => {
+                let time = time.rem_euclid(2.0 * duration);
+                if time > duration {
+                    2.0 * duration - time
+                } else {
+                    time
+                }
+            }
+        }
+    }
+
+    /// Sets the animated properties of the node to their values at the given time
+    pub fn apply(&self, time: f32, node: &mut SceneNode) {
+        let time = self.local_time(time);
+        for track in &self.tracks {
+            if let Some(value) = track.sample(time) {
+                match track.target {
+                    Target::Position => node.position = value,
+                    Target::Rotation => node.rotation = value,
+                    Target::Scale => node.scale = value,
+                }
+            }
+        }
+    }
+}
+
+/// An animation playing on a node, `offset` seconds ahead of the player
+struct Binding {
+    node: *mut SceneNode,
+    animation: Animation,
+    offset: f32,
+}
+
+/// Plays animations on scene nodes, keeping them all in step
+pub struct AnimationPlayer {
+    pub time: f32,
+    pub speed: f32,
+    pub paused: bool,
+    bindings: Vec<Binding>,
+}
+
+impl AnimationPlayer {
+    pub fn new() -> Self {
+        AnimationPlayer {
+            time: 0.0,
+            speed: 1.0,
+            paused: false,
+            bindings: Vec::new(),
+        }
+    }
+
+    /// Plays the animation on the node, `offset` seconds ahead of the player. Like
+    /// children in the scene graph, the node has to outlive the player
+    pub fn add(&mut self, node: &mut SceneNode, animation: Animation, offset: f32) {
+        self.bindings.push(Binding {
+            node: node as *mut SceneNode,
+            animation,
+            offset,
+        });
+    }
+
+    /// Moves time forward and updates every animated node
+    pub fn advance(&mut self, delta_time: f32) {
+        if !self.paused {
+            self.time += delta_time * self.speed;
+        }
+        for binding in &self.bindings {
+            let node = unsafe { &mut *binding.node };
+            binding.animation.apply(self.time + binding.offset, node);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(interpolation: Interpolation) -> Track {
+        Track::new(Target::Position, interpolation)
+            .key(2.0, glm::vec3(2.0, 0.0, 0.0))
+            .key(0.0, glm::vec3(0.0, 0.0, 0.0))
+            .key(1.0, glm::vec3(4.0, 0.0, 0.0))
+    }
+
+    #[test]
+    fn interpolation() {
+        let sample = |interpolation, time| track(interpolation).sample(time).unwrap().x;
+        assert_eq!(sample(Interpolation::Step, 0.9), 0.0);
+        assert_eq!(sample(Interpolation::Linear, 0.5), 2.0);
+        assert_eq!(sample(Interpolation::Linear, 1.5), 3.0);
+        // Goes through every keyframe, and bulges out towards the peak in between
+        for &(time, value) in &[(0.0, 0.0), (1.0, 4.0), (2.0, 2.0)] {
+            assert_eq!(sample(Interpolation::Cubic, time), value);
+        }
+        assert!(sample(Interpolation::Cubic, 0.5) > 2.0);
+        // Times outside the track hold the ends
+        assert_eq!(sample(Interpolation::Cubic, -1.0), 0.0);
+        assert_eq!(sample(Interpolation::Cubic, 3.0), 2.0);
+    }
+
+    #[test]
+    fn repeat() {
+        let animation = |repeat| Animation::new(repeat).with_track(track(Interpolation::Linear));
+        assert_eq!(animation(Repeat::Once).local_time(2.5), 2.0);
+        assert_eq!(animation(Repeat::Loop).local_time(2.5), 0.5);
+        assert_eq!(animation(Repeat::PingPong).local_time(2.5), 1.5);
+        assert_eq!(animation(Repeat::PingPong).local_time(4.5), 0.5);
+    }
+
+    #[test]
+    fn player() {
+        let mut nodes = [SceneNode::new(), SceneNode::new()];
+        let mut player = AnimationPlayer::new();
+        let animation = Animation::new(Repeat::Loop).with_track(track(Interpolation::Linear));
+        player.add(&mut nodes[0], animation.clone(), 0.0);
+        player.add(&mut nodes[1], animation, 0.5);
+
+        player.speed = 0.5;
+        player.advance(1.0);
+        assert_eq!(nodes[0].position.x, 2.0);
+        assert_eq!(nodes[1].position.x, 4.0);
+
+        player.paused = true;
+        player.advance(1.0);
+        assert_eq!(player.time, 0.5);
+        assert_eq!(nodes[0].position.x, 2.0);
+    }
+}
@@ -0,0 +1,182 @@
+use crate::animation::Target;
+use crate::easing::Easing;
+use crate::scene_graph::SceneNode;
+
+/// Where a moving part is between its two poses
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PartState {
+    Closed,
+    Opening,
+    Open,
+    Closing,
+}
+
+/// A part that moves between a closed and an open pose, like a door or a hatch.
+/// Progress goes from 0 when closed to 1 when open, and is eased on the way
+#[derive(Clone, Debug)]
+pub struct Articulation {
+    pub target: Target,
+    pub closed: glm::Vec3,
+    pub open: glm::Vec3,
+    pub duration: f32, // Seconds to go all the way from closed to open
+    pub easing: Easing,
+    state: PartState,
+    progress: f32,
+}
+
+impl Articulation {
+    /// Starts out closed
+    pub fn new(target: Target, closed: glm::Vec3, open: glm::Vec3, duration: f32) -> Self {
+        Articulation {
+            target,
+            closed,
+            open,
+            duration,
+            easing: Easing::EaseInOut,
+            state: PartState::Closed,
+            progress: 0.0,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn state(&self) -> PartState {
+        self.state
+    }
+
+    /// Starts opening, turning around if it was closing
+    pub fn open(&mut self) {
+        if let PartState::Closed | PartState::Closing = self.state {
+            self.state = PartState::Opening;
+        }
+    }
+
+    /// Starts closing, turning around if it was opening
+    pub fn close(&mut self) {
+        if let PartState::Open | PartState::Opening = self.state {
+            self.state = PartState::Closing;
+        }
+    }
+
+    /// Opens if closed or closing, closes otherwise
+    pub fn toggle(&mut self) {
+        match self.state {
+            PartState::Closed | PartState::Closing => self.open(),
+            PartState::Open | PartState::Opening => self.close(),
+        }
+    }
+
+    /// Moves the part along, settling as open or closed once it gets there
+    pub fn update(&mut self, delta_time: f32) {
+        let step = if self.duration > 0.0 {
+            delta_time / self.duration
+        } else {
+            1.0
+        };
+        match self.state {
+            PartState::Opening => {
+                self.progress = (self.progress + step).min(1.0);
+                if self.progress >= 1.0 {
+                    self.state = PartState::Open;
+                }
+            }
+            PartState::Closing => {
+                self.progress = (self.progress - step).max(0.0);
+                if self.progress <= 0.0 {
+                    self.state = PartState::Closed;
+                }
+            }
+            PartState::Closed | PartState::Open => {}
+        }
+    }
+
+    /// The pose between closed and open for the current progress
+    pub fn value(&self) -> glm::Vec3 {
+        let t = self.easing.apply(self.progress);
+        self.closed + (self.open - self.closed) * t
+    }
+
+    /// Puts the node in the current pose
+    pub fn apply(&self, node: &mut SceneNode) {
+        let value = self.value();
+        match self.target {
+            Target::Position => node.position = value,
+            Target::Rotation => node.rotation = value,
+            Target::Scale => node.scale = value,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn door() -> Articulation {
+        Articulation::new(Target::Position, glm::zero(), glm::vec3(0.0, 0.0, 2.0), 1.0)
+    }
+
+    #[test]
+    fn opens_and_closes() {
+        let mut door = door();
+        door.update(0.5);
+        assert_eq!(door.state(), PartState::Closed);
+        assert_eq!(door.value(), glm::Vec3::zeros());
+
+        door.toggle();
+        assert_eq!(door.state(), PartState::Opening);
+        door.update(0.5);
+        assert_eq!(door.state(), PartState::Opening);
+        assert_eq!(door.value(), glm::vec3(0.0, 0.0, 1.0));
+        door.update(0.6);
+        assert_eq!(door.state(), PartState::Open);
+        assert_eq!(door.value(), glm::vec3(0.0, 0.0, 2.0));
+
+        // Opening again changes nothing
+        door.open();
+        assert_eq!(door.state(), PartState::Open);
+        door.toggle();
+        assert_eq!(door.state(), PartState::Closing);
+        door.update(2.0);
+        assert_eq!(door.state(), PartState::Closed);
+        assert_eq!(door.value(), glm::Vec3::zeros());
+    }
+
+    #[test]
+    fn turns_around() {
+        let mut door = door();
+        door.easing = Easing::Linear;
+        door.open();
+        door.update(0.25);
+        door.close();
+        assert_eq!(door.state(), PartState::Closing);
+        // Goes back from where it was, rather than jumping to either end
+        door.update(0.1);
+        assert!((door.value().z - 0.3).abs() < 1e-5);
+        door.toggle();
+        assert_eq!(door.state(), PartState::Opening);
+        door.update(0.85);
+        assert_eq!(door.state(), PartState::Open);
+    }
+
+    #[test]
+    fn eases_and_applies() {
+        let mut door = door();
+        door.target = Target::Rotation;
+        door.open();
+        door.update(0.25);
+        let expected = 2.0 * Easing::EaseInOut.apply(0.25);
+        assert!((door.value().z - expected).abs() < 1e-5);
+        assert!(door.value().z < 0.5);
+
+        let mut node = SceneNode::new();
+        door.apply(&mut node);
+        assert_eq!(node.rotation, door.value());
+        assert_eq!(node.position, glm::Vec3::zeros());
+
+        // Without a duration it gets there right away
+        let mut instant =
+            Articulation::new(Target::Scale, glm::zero(), glm::vec3(1.0, 1.0, 1.0), 0.0);
+        instant.open();
+        instant.update(0.0);
+        assert_eq!(instant.state(), PartState::Open);
+    }
+}
@@ -1,11 +1,151 @@
-// Constants for making movement a bit more "comfortable"
+// Defaults for making movement a bit more "comfortable"
 const MOVEMENT_SPEED: f32 = 100.0;
+const SPRINT_MULTIPLIER: f32 = 3.0;
+const ACCELERATION: f32 = 8.0;
+const DAMPING: f32 = 5.0;
 const SENSITIVITY: f32 = 0.001;
+// Scrolling multiplies the speed by this for every line, within these limits
+const SPEED_STEP: f32 = 1.1;
+const MIN_SPEED: f32 = 1.0;
+const MAX_SPEED: f32 = 2000.0;
 
 // Allow looking straight up and down
 const PITCH_MAX: f32 = std::f32::consts::PI / 2.0;
 const YAW_MAX: f32 = std::f32::consts::PI;
 
+// Default projection, the aspect ratio should be set from the window
+const DEFAULT_FOVY: f32 = 60.0;
+const DEFAULT_NEAR: f32 = 1.0;
+const DEFAULT_FAR: f32 = 1000.0;
+const DEFAULT_ORTHO_HEIGHT: f32 = 500.0;
+
+// Orbit controller tuning. Zooming multiplies the distance by ZOOM_STEP for every
+// line scrolled, and panning is relative to the distance
+const ORBIT_SENSITIVITY: f32 = 0.005;
+const ZOOM_STEP: f32 = 0.9;
+const PAN_SENSITIVITY: f32 = 0.001;
+const MIN_DISTANCE: f32 = 1.0;
+
+// Chase controller springs, per unit of mass. Springs are stepped at least this often,
+// so they stay stable at low frame rates
+const CHASE_STIFFNESS: f32 = 20.0;
+const MAX_SPRING_STEP: f32 = 1.0 / 120.0;
+
+/// Which controller moves the camera around
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Controller {
+    FreeFly,
+    Orbit,
+    Chase,
+    Path, // Playing back a camera path
+}
+
+/// How the camera projects the world onto the screen
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    Perspective,
+    Orthographic,
+}
+
+/// Approximate height of a sphere on screen, as a fraction of the screen height, given
+/// the combined view and projection transform of the camera looking at it
+pub fn projected_size(view_projection: &glm::Mat4, center: &glm::Vec3, radius: f32) -> f32 {
+    let clip = view_projection * glm::vec4(center.x, center.y, center.z, 1.0);
+    // The view transform only rotates and translates, so the length of this row is the
+    // vertical scaling done by the projection
+    let vertical_scale = glm::length(&glm::vec3(
+        view_projection[(1, 0)],
+        view_projection[(1, 1)],
+        view_projection[(1, 2)],
+    ));
+    // Orthographic projections keep w at 1, so there is no getting too close
+    let perspective = view_projection[(3, 0)] != 0.0
+        || view_projection[(3, 1)] != 0.0
+        || view_projection[(3, 2)] != 0.0;
+    if perspective && clip.w <= radius {
+        // Close enough to cover the whole screen
+        return f32::INFINITY;
+    }
+    radius * vertical_scale / clip.w
+}
+
+/// Direction the camera looks in with the given yaw and pitch. Positive pitch looks down
+fn look_direction(yaw: f32, pitch: f32) -> glm::Vec3 {
+    glm::vec3(
+        yaw.sin() * pitch.cos(),
+        -pitch.sin(),
+        -yaw.cos() * pitch.cos(),
+    )
+}
+
+/// Planes of the view frustum of the given view and projection transform, as (normal, d)
+/// with the normals pointing inwards (Gribb and Hartmann)
+pub fn frustum_planes(view_projection: &glm::Mat4) -> [glm::Vec4; 6] {
+    let row = |i: usize| view_projection.row(i).transpose();
+    let planes = [
+        row(3) + row(0), // Left
+        row(3) - row(0), // Right
+        row(3) + row(1), // Bottom
+        row(3) - row(1), // Top
+        row(3) + row(2), // Near
+        row(3) - row(2), // Far
+    ];
+    // Planes at infinity have no normal, so make them let everything through
+    let normalize = |p: &glm::Vec4| {
+        let length = glm::length(&p.xyz());
+        if length > 0.0 {
+            p / length
+        } else {
+            glm::vec4(0.0, 0.0, 0.0, 1.0)
+        }
+    };
+    [
+        normalize(&planes[0]),
+        normalize(&planes[1]),
+        normalize(&planes[2]),
+        normalize(&planes[3]),
+        normalize(&planes[4]),
+        normalize(&planes[5]),
+    ]
+}
+
+/// Whether any part of the axis aligned box might be inside the frustum
+pub fn box_in_frustum(planes: &[glm::Vec4; 6], min: &glm::Vec3, max: &glm::Vec3) -> bool {
+    planes.iter().all(|plane| {
+        // The corner furthest along the normal of the plane
+        let corner = glm::vec3(
+            if plane.x >= 0.0 { max.x } else { min.x },
+            if plane.y >= 0.0 { max.y } else { min.y },
+            if plane.z >= 0.0 { max.z } else { min.z },
+        );
+        glm::dot(&plane.xyz(), &corner) + plane.w >= 0.0
+    })
+}
+
+/// Tunable behaviour of the free-fly controller
+#[derive(Clone, Copy, Debug)]
+pub struct FlySettings {
+    pub speed: f32, // Top speed in units per second
+    pub sprint_multiplier: f32,
+    pub acceleration: f32, // How quickly the top speed is reached, per second
+    pub damping: f32,      // How quickly the camera stops once let go, per second
+    pub sensitivity: f32,  // Radians per pixel of mouse movement
+    pub invert_y: bool,
+}
+
+impl Default for FlySettings {
+    fn default() -> Self {
+        FlySettings {
+            speed: MOVEMENT_SPEED,
+            sprint_multiplier: SPRINT_MULTIPLIER,
+            acceleration: ACCELERATION,
+            damping: DAMPING,
+            sensitivity: SENSITIVITY,
+            invert_y: false,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Camera {
     pub x: f32,
@@ -13,6 +153,18 @@ pub struct Camera {
     pub z: f32,
     pub yaw: f32,
     pub pitch: f32,
+    pub velocity: glm::Vec3,
+    pub settings: FlySettings,
+    pub projection: Projection,
+    pub fovy: f32,         // Vertical field of view in degrees, when in perspective
+    pub ortho_height: f32, // Height of the view volume, when orthographic
+    pub aspect: f32,
+    pub near: f32,
+    pub far: f32,
+    // Map the near plane to depth 1 and infinity (or the far plane, when orthographic)
+    // to depth 0, which spreads depth precision much more evenly. Needs a depth range
+    // of 0 to 1 (glClipControl), clearing depth to 0 and GL_GREATER depth testing
+    pub reversed_z: bool,
 }
 
 impl Camera {
@@ -23,64 +175,266 @@ impl Camera {
             z: 0.0,
             yaw: 0.0,
             pitch: 0.0,
+            velocity: glm::zero(),
+            settings: FlySettings::default(),
+            projection: Projection::Perspective,
+            fovy: DEFAULT_FOVY,
+            ortho_height: DEFAULT_ORTHO_HEIGHT,
+            aspect: 1.0,
+            near: DEFAULT_NEAR,
+            far: DEFAULT_FAR,
+            reversed_z: false,
         }
     }
 
-    /// Makes a view projection matrix for the current camera position
-    pub fn make_view_transform(&self, fovy: f32) -> glm::Mat4 {
+    /// Where the camera is in the world
+    pub fn position(&self) -> glm::Vec3 {
+        glm::vec3(self.x, self.y, self.z)
+    }
+
+    /// Direction the camera is looking in
+    pub fn forward(&self) -> glm::Vec3 {
+        look_direction(self.yaw, self.pitch)
+    }
+
+    /// Direction to the right of the camera, along the ground
+    pub fn right(&self) -> glm::Vec3 {
+        glm::vec3(self.yaw.cos(), 0.0, self.yaw.sin())
+    }
+
+    /// Direction straight up on the screen
+    pub fn up(&self) -> glm::Vec3 {
+        glm::cross(&self.right(), &self.forward())
+    }
+
+    /// Sets the aspect ratio from the size of the window (or viewport) in pixels
+    pub fn set_viewport_size(&mut self, width: u32, height: u32) {
+        self.aspect = width as f32 / height.max(1) as f32;
+    }
+
+    /// Transforms from world space to view space, where the camera sits at the origin
+    /// looking down -z
+    pub fn view_matrix(&self) -> glm::Mat4 {
         let mut transformation: glm::Mat4 = glm::Mat4::identity();
-        transformation =
-            glm::translation(&glm::vec3(-self.x, -self.y, -self.z - 2.0)) * transformation;
+        transformation = glm::translation(&glm::vec3(-self.x, -self.y, -self.z)) * transformation;
         transformation = glm::rotation(self.yaw, &glm::vec3(0.0, 1.0, 0.0)) * transformation;
         transformation = glm::rotation(self.pitch, &glm::vec3(1.0, 0.0, 0.0)) * transformation;
-        transformation = glm::perspective(fovy, 45f32, 1.0, 1000.0) * transformation;
         transformation
     }
 
+    /// Transforms from view space to clip space
+    pub fn projection_matrix(&self) -> glm::Mat4 {
+        match self.projection {
+            Projection::Perspective => {
+                let fovy = self.fovy.to_radians();
+                if self.reversed_z {
+                    glm::reversed_infinite_perspective_rh_zo(self.aspect, fovy, self.near)
+                } else {
+                    glm::perspective(self.aspect, fovy, self.near, self.far)
+                }
+            }
+            Projection::Orthographic => {
+                let top = self.ortho_height / 2.0;
+                let right = top * self.aspect;
+                if self.reversed_z {
+                    // Swapping near and far maps the near plane to 1 and the far plane to 0
+                    glm::ortho_rh_zo(-right, right, -top, top, self.far, self.near)
+                } else {
+                    glm::ortho(-right, right, -top, top, self.near, self.far)
+                }
+            }
+        }
+    }
+
+    /// Switches between perspective and orthographic projection
+    pub fn toggle_projection(&mut self) {
+        self.projection = match self.projection {
+            Projection::Perspective => Projection::Orthographic,
+            Projection::Orthographic => Projection::Perspective,
+        };
+    }
+
+    /// Makes a view projection matrix for the current camera position
+    pub fn make_view_transform(&self) -> glm::Mat4 {
+        self.projection_matrix() * self.view_matrix()
+    }
+
     /// Updates yaw and pitch based on mouse delta
     pub fn move_mouse(&mut self, x: f32, y: f32) {
-        self.yaw = self.yaw + x * SENSITIVITY;
+        let y = if self.settings.invert_y { -y } else { y };
+
+        self.yaw += x * self.settings.sensitivity;
         if self.yaw > YAW_MAX {
             self.yaw -= 2.0 * YAW_MAX;
         } else if self.yaw < -YAW_MAX {
             self.yaw += 2.0 * YAW_MAX;
         }
 
-        self.pitch = self.pitch + y * SENSITIVITY;
-        if self.pitch > PITCH_MAX {
-            self.pitch = PITCH_MAX;
-        } else if self.pitch < -PITCH_MAX {
-            self.pitch = -PITCH_MAX;
+        self.pitch = (self.pitch + y * self.settings.sensitivity).clamp(-PITCH_MAX, PITCH_MAX);
+    }
+
+    /// Speeds up towards the given direction, or slows down if it is zero, and moves.
+    /// The direction is relative to the view: x goes right, y up and z forward
+    pub fn fly(&mut self, direction: &glm::Vec3, sprint: bool, delta_time: f32) {
+        let world =
+            self.right() * direction.x + self.up() * direction.y + self.forward() * direction.z;
+        let (target, rate) = if glm::length(&world) > 0.0 {
+            let speed = if sprint {
+                self.settings.speed * self.settings.sprint_multiplier
+            } else {
+                self.settings.speed
+            };
+            (glm::normalize(&world) * speed, self.settings.acceleration)
+        } else {
+            (glm::zero(), self.settings.damping)
+        };
+
+        // Exponential approach, so the motion is the same at any frame rate
+        let blend = 1.0 - (-rate * delta_time).exp();
+        self.velocity += (target - self.velocity) * blend;
+        self.x += self.velocity.x * delta_time;
+        self.y += self.velocity.y * delta_time;
+        self.z += self.velocity.z * delta_time;
+    }
+
+    /// Makes the top speed faster for positive amounts of lines scrolled, and slower
+    /// for negative
+    pub fn adjust_speed(&mut self, lines: f32) {
+        self.settings.speed =
+            (self.settings.speed * SPEED_STEP.powf(lines)).clamp(MIN_SPEED, MAX_SPEED);
+    }
+}
+
+/// Keeps the camera looking at a target from some distance away. Dragging rotates
+/// around the target, scrolling zooms in and out and panning moves the target
+#[derive(Debug)]
+pub struct OrbitController {
+    pub target: glm::Vec3,
+    pub distance: f32,
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+impl OrbitController {
+    /// Orbits the point `distance` in front of the camera, so the view doesn't jump
+    /// when switching controllers
+    pub fn from_camera(camera: &Camera, distance: f32) -> Self {
+        OrbitController {
+            target: camera.position() + camera.forward() * distance,
+            distance,
+            yaw: camera.yaw,
+            pitch: camera.pitch,
         }
     }
 
-    // These are pretty self explanatory
+    /// Rotates around the target based on mouse delta
+    pub fn rotate(&mut self, x: f32, y: f32) {
+        self.yaw += x * ORBIT_SENSITIVITY;
+        self.pitch = (self.pitch + y * ORBIT_SENSITIVITY).clamp(-PITCH_MAX, PITCH_MAX);
+    }
 
-    pub fn move_forward(&mut self, delta_time: f32) {
-        self.x += MOVEMENT_SPEED * delta_time * self.yaw.sin();
-        self.z -= MOVEMENT_SPEED * delta_time * self.yaw.cos();
+    /// Moves closer for positive amounts of lines scrolled, and further away for negative
+    pub fn zoom(&mut self, lines: f32) {
+        self.distance = (self.distance * ZOOM_STEP.powf(lines)).max(MIN_DISTANCE);
     }
 
-    pub fn move_backward(&mut self, delta_time: f32) {
-        self.x -= MOVEMENT_SPEED * delta_time * self.yaw.sin();
-        self.z += MOVEMENT_SPEED * delta_time * self.yaw.cos();
+    /// Moves the target along the screen based on mouse delta, so the scene follows
+    /// the mouse
+    pub fn pan(&mut self, x: f32, y: f32) {
+        let forward = look_direction(self.yaw, self.pitch);
+        let right = glm::vec3(self.yaw.cos(), 0.0, self.yaw.sin());
+        let up = glm::cross(&right, &forward);
+        let scale = self.distance * PAN_SENSITIVITY;
+        self.target += (up * y - right * x) * scale;
     }
 
-    pub fn move_left(&mut self, delta_time: f32) {
-        self.x -= MOVEMENT_SPEED * delta_time * self.yaw.cos();
-        self.z -= MOVEMENT_SPEED * delta_time * self.yaw.sin();
+    /// Places the camera so it looks at the target
+    pub fn apply(&self, camera: &mut Camera) {
+        let position = self.target - look_direction(self.yaw, self.pitch) * self.distance;
+        camera.x = position.x;
+        camera.y = position.y;
+        camera.z = position.z;
+        camera.yaw = self.yaw;
+        camera.pitch = self.pitch;
     }
+}
 
-    pub fn move_right(&mut self, delta_time: f32) {
-        self.x += MOVEMENT_SPEED * delta_time * self.yaw.cos();
-        self.z += MOVEMENT_SPEED * delta_time * self.yaw.sin();
+/// A point pulled towards a target by a spring, slowed down by a damper
+#[derive(Debug)]
+struct Spring {
+    value: glm::Vec3,
+    velocity: glm::Vec3,
+}
+
+impl Spring {
+    fn update(&mut self, target: &glm::Vec3, stiffness: f32, damping: f32, delta_time: f32) {
+        let steps = (delta_time / MAX_SPRING_STEP).ceil().max(1.0);
+        let step = delta_time / steps;
+        for _ in 0..steps as u32 {
+            let acceleration = (target - self.value) * stiffness - self.velocity * damping;
+            self.velocity += acceleration * step;
+            self.value += self.velocity * step;
+        }
     }
+}
 
-    pub fn move_up(&mut self, delta_time: f32) {
-        self.y += MOVEMENT_SPEED * delta_time;
+/// Follows something that moves, sitting at an offset in its local frame and looking
+/// at another point in that frame. Springs smooth out the motion, so the camera
+/// lags a little behind sudden turns
+#[derive(Debug)]
+pub struct ChaseController {
+    pub offset: glm::Vec3,
+    pub look_at: glm::Vec3,
+    pub stiffness: f32,
+    pub damping: f32,
+    position: Spring,
+    look: Spring,
+}
+
+impl ChaseController {
+    /// Starts out where the camera is, so it glides over to what it follows instead
+    /// of jumping there
+    pub fn from_camera(camera: &Camera, offset: glm::Vec3, look_at: glm::Vec3) -> Self {
+        let position = camera.position();
+        ChaseController {
+            offset,
+            look_at,
+            stiffness: CHASE_STIFFNESS,
+            // Critically damped, so it settles quickly without overshooting
+            damping: 2.0 * CHASE_STIFFNESS.sqrt(),
+            position: Spring {
+                value: position,
+                velocity: glm::zero(),
+            },
+            look: Spring {
+                value: position + camera.forward(),
+                velocity: glm::zero(),
+            },
+        }
     }
 
-    pub fn move_down(&mut self, delta_time: f32) {
-        self.y -= MOVEMENT_SPEED * delta_time;
+    /// Moves towards the offset, given the current transformation of what to follow
+    pub fn update(&mut self, transform: &glm::Mat4, delta_time: f32) {
+        let to_world = |p: &glm::Vec3| (transform * glm::vec4(p.x, p.y, p.z, 1.0)).xyz();
+        let (position, look_at) = (to_world(&self.offset), to_world(&self.look_at));
+        self.position
+            .update(&position, self.stiffness, self.damping, delta_time);
+        self.look
+            .update(&look_at, self.stiffness, self.damping, delta_time);
+    }
+
+    /// Places the camera where the springs are, looking at the look at point
+    pub fn apply(&self, camera: &mut Camera) {
+        let position = self.position.value;
+        camera.x = position.x;
+        camera.y = position.y;
+        camera.z = position.z;
+
+        let direction = self.look.value - position;
+        if glm::length(&direction) > 0.0 {
+            let direction = glm::normalize(&direction);
+            camera.yaw = direction.x.atan2(-direction.z);
+            camera.pitch = (-direction.y).clamp(-1.0, 1.0).asin();
+        }
     }
 }
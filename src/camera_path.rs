@@ -0,0 +1,379 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::camera::Camera;
+use crate::easing::Easing;
+
+/// Curve the camera follows between keys
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Spline {
+    CatmullRom, // Passes through every key, shaped by the keys around it
+    Bezier,     // Shaped by the handles of the keys
+}
+
+/// Where the camera is and which way it looks at some point in time. The easing is used
+/// on the way to the next key
+#[derive(Clone, Debug)]
+pub struct CameraKey {
+    pub time: f32, // Seconds from the start of the path
+    pub position: glm::Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub easing: Easing,
+    // Control points before and after the key, for Bezier paths. Made from the
+    // neighbouring keys if missing, which gives the same curve as Catmull-Rom
+    pub handles: Option<(glm::Vec3, glm::Vec3)>,
+}
+
+/// A camera flythrough, given as keys along a timeline
+#[derive(Clone, Debug)]
+pub struct CameraPath {
+    pub spline: Spline,
+    pub looping: bool,
+    pub keys: Vec<CameraKey>, // Sorted by time
+}
+
+/// Uniform Catmull-Rom spline through `b` and `c` at `t` from 0 to 1
+fn catmull_rom<T>(a: T, b: T, c: T, d: T, t: f32) -> T
+where
+    T: Copy
+        + std::ops::Add<Output = T>
+        + std::ops::Sub<Output = T>
+        + std::ops::Mul<f32, Output = T>,
+{
+    let (t2, t3) = (t * t, t * t * t);
+    (b * 2.0
+        + (c - a) * t
+        + (a * 2.0 - b * 5.0 + c * 4.0 - d) * t2
+        + (b * 3.0 - a - c * 3.0 + d) * t3)
+        * 0.5
+}
+
+/// Cubic Bezier curve from `a` to `d` at `t` from 0 to 1
+fn bezier(a: glm::Vec3, b: glm::Vec3, c: glm::Vec3, d: glm::Vec3, t: f32) -> glm::Vec3 {
+    let s = 1.0 - t;
+    a * (s * s * s) + b * (3.0 * s * s * t) + c * (3.0 * s * t * t) + d * (t * t * t)
+}
+
+/// The same angle as `angle`, give or take full turns, but as close to `reference` as
+/// possible, so interpolating between them takes the short way around
+fn unwrap_angle(angle: f32, reference: f32) -> f32 {
+    let full_turn = 2.0 * std::f32::consts::PI;
+    angle - ((angle - reference) / full_turn).round() * full_turn
+}
+
+fn parse_numbers(fields: &[&str]) -> Result<Vec<f32>, String> {
+    fields
+        .iter()
+        .map(|field| {
+            field
+                .parse::<f32>()
+                .map_err(|_| format!("'{}' is not a number", field))
+        })
+        .collect()
+}
+
+impl CameraPath {
+    pub fn new(spline: Spline) -> Self {
+        CameraPath {
+            spline,
+            looping: false,
+            keys: Vec::new(),
+        }
+    }
+
+    /// Adds a key, keeping the keys sorted by time
+    pub fn add_key(&mut self, key: CameraKey) {
+        let index = self.keys.iter().take_while(|k| k.time <= key.time).count();
+        self.keys.insert(index, key);
+    }
+
+    /// Adds a key where the camera is right now, `interval` seconds after the last one
+    pub fn add_camera_key(&mut self, camera: &Camera, interval: f32) {
+        let time = match self.keys.last() {
+            Some(key) => key.time + interval,
+            None => 0.0,
+        };
+        self.add_key(CameraKey {
+            time,
+            position: camera.position(),
+            yaw: camera.yaw,
+            pitch: camera.pitch,
+            easing: Easing::Linear,
+            handles: None,
+        });
+    }
+
+    /// Time of the last key
+    pub fn duration(&self) -> f32 {
+        self.keys.last().map_or(0.0, |key| key.time)
+    }
+
+    /// Whether the given time is past the end of the path. Looping paths never end
+    pub fn finished(&self, time: f32) -> bool {
+        !self.looping && time > self.duration()
+    }
+
+    /// Position, yaw and pitch of the camera at the given time, or None if there are no
+    /// keys. Times outside the timeline hold the first or last key, unless the path loops
+    pub fn sample(&self, time: f32) -> Option<(glm::Vec3, f32, f32)> {
+        let first = self.keys.first()?;
+        let last = self.keys.last()?;
+        let length = last.time - first.time;
+        let time = if self.looping && length > 0.0 {
+            first.time + (time - first.time).rem_euclid(length)
+        } else {
+            time
+        };
+
+        let next = self.keys.iter().position(|key| key.time > time);
+        let i = match next {
+            None => return Some((last.position, last.yaw, last.pitch)),
+            Some(0) => return Some((first.position, first.yaw, first.pitch)),
+            Some(next) => next - 1,
+        };
+        let (from, to) = (&self.keys[i], &self.keys[i + 1]);
+        let t = from
+            .easing
+            .apply((time - from.time) / (to.time - from.time));
+
+        // Missing neighbours at the ends are mirrored, so the path starts and stops
+        // heading straight for the next key
+        let position = |j: usize| self.keys[j].position;
+        let (p1, p2) = (position(i), position(i + 1));
+        let p0 = if i > 0 {
+            position(i - 1)
+        } else {
+            p1 * 2.0 - p2
+        };
+        let p3 = if i + 2 < self.keys.len() {
+            position(i + 2)
+        } else {
+            p2 * 2.0 - p1
+        };
+        let position = match self.spline {
+            Spline::CatmullRom => catmull_rom(p0, p1, p2, p3, t),
+            Spline::Bezier => {
+                let out_handle = from.handles.map_or(p1 + (p2 - p0) / 6.0, |h| h.1);
+                let in_handle = to.handles.map_or(p2 - (p3 - p1) / 6.0, |h| h.0);
+                bezier(p1, out_handle, in_handle, p2, t)
+            }
+        };
+
+        // Angles always follow Catmull-Rom, after unwrapping the yaw so the camera
+        // doesn't spin the long way around
+        let yaw1 = from.yaw;
+        let yaw0 = if i > 0 {
+            unwrap_angle(self.keys[i - 1].yaw, yaw1)
+        } else {
+            yaw1
+        };
+        let yaw2 = unwrap_angle(to.yaw, yaw1);
+        let yaw3 = if i + 2 < self.keys.len() {
+            unwrap_angle(self.keys[i + 2].yaw, yaw2)
+        } else {
+            yaw2
+        };
+        let pitch = |j: usize| self.keys[j.min(self.keys.len() - 1)].pitch;
+        let pitch0 = pitch(i.saturating_sub(1));
+        let yaw = catmull_rom(yaw0, yaw1, yaw2, yaw3, t);
+        let pitch = catmull_rom(pitch0, pitch(i), pitch(i + 1), pitch(i + 2), t);
+
+        Some((position, yaw, pitch))
+    }
+
+    /// Places the camera where the path is at the given time. Does nothing if there are
+    /// no keys
+    pub fn apply(&self, time: f32, camera: &mut Camera) {
+        if let Some((position, yaw, pitch)) = self.sample(time) {
+            camera.x = position.x;
+            camera.y = position.y;
+            camera.z = position.z;
+            camera.yaw = unwrap_angle(yaw, 0.0);
+            camera.pitch = pitch;
+        }
+    }
+
+    /// Parses a path from text. Each line is either `spline catmull-rom|bezier`,
+    /// `loop true|false`, or a key given as `key time x y z yaw pitch [easing]`,
+    /// optionally followed by `handles` and the in and out handles as x y z x y z.
+    /// Angles are in radians, and lines starting with # are comments
+    pub fn parse(text: &str) -> Result<CameraPath, String> {
+        let mut path = CameraPath::new(Spline::CatmullRom);
+        for (number, line) in text.lines().enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let error = |message: String| format!("Line {}: {}", number + 1, message);
+            match fields.as_slice() {
+                [] => {}
+                [comment, ..] if comment.starts_with('#') => {}
+                ["spline", "catmull-rom"] => path.spline = Spline::CatmullRom,
+                ["spline", "bezier"] => path.spline = Spline::Bezier,
+                ["loop", "true"] => path.looping = true,
+                ["loop", "false"] => path.looping = false,
+                ["key", rest @ ..] if rest.len() >= 6 => {
+                    let values = parse_numbers(&rest[..6]).map_err(error)?;
+                    let mut rest = &rest[6..];
+                    let mut easing = Easing::Linear;
+                    if let Some(name) = rest.first().filter(|&&name| name != "handles") {
+                        easing = Easing::from_name(name)
+                            .ok_or_else(|| error(format!("Unknown easing '{}'", name)))?;
+                        rest = &rest[1..];
+                    }
+                    let handles = match rest {
+                        [] => None,
+                        ["handles", handles @ ..] if handles.len() == 6 => {
+                            let h = parse_numbers(handles).map_err(error)?;
+                            Some((glm::vec3(h[0], h[1], h[2]), glm::vec3(h[3], h[4], h[5])))
+                        }
+                        _ => return Err(error("Expected 'handles' and six numbers".to_string())),
+                    };
+                    path.add_key(CameraKey {
+                        time: values[0],
+                        position: glm::vec3(values[1], values[2], values[3]),
+                        yaw: values[4],
+                        pitch: values[5],
+                        easing,
+                        handles,
+                    });
+                }
+                _ => return Err(error(format!("Can't make sense of '{}'", line.trim()))),
+            }
+        }
+        Ok(path)
+    }
+
+    pub fn load(path: &str) -> Result<CameraPath, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        CameraPath::parse(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    /// Writes the path in the format `parse` reads
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(
+            file,
+            "# key time x y z yaw pitch [easing] [handles x y z x y z]"
+        )?;
+        let spline = match self.spline {
+            Spline::CatmullRom => "catmull-rom",
+            Spline::Bezier => "bezier",
+        };
+        writeln!(file, "spline {}", spline)?;
+        writeln!(file, "loop {}", self.looping)?;
+        for key in &self.keys {
+            write!(
+                file,
+                "key {} {} {} {} {} {} {}",
+                key.time,
+                key.position.x,
+                key.position.y,
+                key.position.z,
+                key.yaw,
+                key.pitch,
+                key.easing.name()
+            )?;
+            if let Some((h_in, h_out)) = key.handles {
+                write!(
+                    file,
+                    " handles {} {} {} {} {} {}",
+                    h_in.x, h_in.y, h_in.z, h_out.x, h_out.y, h_out.z
+                )?;
+            }
+            writeln!(file)?;
+        }
+        file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A path through four keys with all kinds of easing, one of them with handles
+    fn path(spline: Spline) -> CameraPath {
+        let mut path = CameraPath::new(spline);
+        let easings = [
+            Easing::Linear,
+            Easing::EaseIn,
+            Easing::EaseOut,
+            Easing::EaseInOut,
+        ];
+        for (i, &easing) in easings.iter().enumerate() {
+            let i = i as f32;
+            path.add_key(CameraKey {
+                time: i * 1.5,
+                position: glm::vec3(i * 10.0, (i * 2.0).sin() * 5.0, -i * i),
+                yaw: 3.0 - i * 2.1, // Crosses over a full turn between the keys
+                pitch: 0.1 * i - 0.2,
+                easing,
+                handles: None,
+            });
+        }
+        path.keys[1].handles = Some((glm::vec3(5.0, 4.0, 0.0), glm::vec3(14.0, -2.0, 1.0)));
+        path
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut original = path(Spline::Bezier);
+        original.looping = true;
+        let file = std::env::temp_dir().join("gloom-rs-test.path");
+        let file = file.to_str().unwrap();
+        original.save(file).unwrap();
+        let loaded = CameraPath::load(file).unwrap();
+        std::fs::remove_file(file).unwrap();
+
+        assert_eq!(loaded.spline, original.spline);
+        assert_eq!(loaded.looping, original.looping);
+        assert_eq!(loaded.keys.len(), original.keys.len());
+        for (loaded, original) in loaded.keys.iter().zip(&original.keys) {
+            assert_eq!(loaded.time, original.time);
+            assert_eq!(loaded.position, original.position);
+            assert_eq!(loaded.yaw, original.yaw);
+            assert_eq!(loaded.pitch, original.pitch);
+            assert_eq!(loaded.easing, original.easing);
+            assert_eq!(loaded.handles, original.handles);
+        }
+
+        assert!(CameraPath::parse("key 0 1 2 3 4 5 sideways").is_err());
+        assert!(CameraPath::parse("key 0 1 2 3 4 5 handles 1 2 3").is_err());
+    }
+
+    #[test]
+    fn passes_through_keys() {
+        for &spline in &[Spline::CatmullRom, Spline::Bezier] {
+            let path = path(spline);
+            for key in &path.keys {
+                let (position, yaw, pitch) = path.sample(key.time).unwrap();
+                assert!(
+                    glm::distance(&position, &key.position) < 1e-4,
+                    "{:?} misses {:?} at {}",
+                    spline,
+                    key.position,
+                    key.time
+                );
+                assert!((unwrap_angle(yaw, key.yaw) - key.yaw).abs() < 1e-4);
+                assert!((pitch - key.pitch).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn easing() {
+        // Evenly spaced keys along a line, so the camera is where the eased time says
+        let mut path = CameraPath::new(Spline::CatmullRom);
+        for i in 0..3 {
+            path.add_key(CameraKey {
+                time: i as f32,
+                position: glm::vec3(i as f32 * 10.0, 0.0, 0.0),
+                yaw: 0.0,
+                pitch: 0.0,
+                easing: Easing::EaseIn,
+                handles: None,
+            });
+        }
+        let x = |time: f32| path.sample(time).unwrap().0.x;
+        assert!((x(1.5) - 10.0 - 10.0 * Easing::EaseIn.apply(0.5)).abs() < 1e-4);
+        assert!(x(1.5) < 15.0);
+    }
+}
@@ -0,0 +1,79 @@
+/// An offscreen framebuffer that frames are drawn into and saved from as images, so
+/// captures don't depend on the window being visible or on what the back buffer holds
+pub struct FrameCapture {
+    framebuffer_id: u32,
+    width: u32,
+    height: u32,
+    directory: String,
+    pub frames: usize,
+}
+
+impl FrameCapture {
+    /// Creates a framebuffer with color and depth renderbuffers of the given size, and
+    /// the directory the frames are saved in
+    pub unsafe fn new(directory: &str, width: u32, height: u32) -> Self {
+        std::fs::create_dir_all(directory)
+            .unwrap_or_else(|e| panic!("Failed to create {}: {}", directory, e));
+
+        let mut framebuffer_id = 0u32;
+        gl::GenFramebuffers(1, &mut framebuffer_id as *mut u32);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer_id);
+
+        let mut renderbuffer_ids = [0u32; 2];
+        gl::GenRenderbuffers(2, renderbuffer_ids.as_mut_ptr());
+        let attachments = [
+            (gl::RGBA8, gl::COLOR_ATTACHMENT0),
+            // Floating point depth, so reversed Z keeps its precision
+            (gl::DEPTH_COMPONENT32F, gl::DEPTH_ATTACHMENT),
+        ];
+        for (&id, &(format, attachment)) in renderbuffer_ids.iter().zip(attachments.iter()) {
+            gl::BindRenderbuffer(gl::RENDERBUFFER, id);
+            gl::RenderbufferStorage(gl::RENDERBUFFER, format, width as i32, height as i32);
+            gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, attachment, gl::RENDERBUFFER, id);
+        }
+
+        let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            panic!("Capture framebuffer is incomplete: {:#x}", status);
+        }
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+
+        FrameCapture {
+            framebuffer_id,
+            width,
+            height,
+            directory: directory.to_string(),
+            frames: 0,
+        }
+    }
+
+    /// Makes the following draw calls draw into the capture instead of the window
+    pub unsafe fn bind(&self) {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer_id);
+        gl::Viewport(0, 0, self.width as i32, self.height as i32);
+    }
+
+    /// Saves what has been drawn into the capture so far as the next numbered image
+    pub unsafe fn save_frame(&mut self) {
+        let mut pixels = vec![0u8; (self.width * self.height * 3) as usize];
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.framebuffer_id);
+        gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+        gl::ReadPixels(
+            0,
+            0,
+            self.width as i32,
+            self.height as i32,
+            gl::RGB,
+            gl::UNSIGNED_BYTE,
+            pixels.as_mut_ptr() as *mut std::ffi::c_void,
+        );
+
+        let path = format!("{}/frame_{:05}.png", self.directory, self.frames);
+        // OpenGL starts at the bottom row, images at the top
+        let image = image::RgbImage::from_raw(self.width, self.height, pixels).unwrap();
+        if let Err(e) = image::imageops::flip_vertical(&image).save(&path) {
+            println!("Failed to save frame {}: {}", path, e);
+        }
+        self.frames += 1;
+    }
+}
@@ -0,0 +1,48 @@
+/// How a transition speeds up and slows down. Maps progress from 0 to 1 onto eased
+/// progress from 0 to 1
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    /// Eased progress for the given progress, which is clamped to 0..1
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+            Easing::EaseInOut => {
+                if t < 0.5 {
+                    4.0 * t * t * t
+                } else {
+                    1.0 - (2.0 - 2.0 * t).powi(3) / 2.0
+                }
+            }
+        }
+    }
+
+    /// Name used when saving to files
+    pub fn name(&self) -> &'static str {
+        match self {
+            Easing::Linear => "linear",
+            Easing::EaseIn => "in",
+            Easing::EaseOut => "out",
+            Easing::EaseInOut => "in-out",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Easing> {
+        match name {
+            "linear" => Some(Easing::Linear),
+            "in" => Some(Easing::EaseIn),
+            "out" => Some(Easing::EaseOut),
+            "in-out" => Some(Easing::EaseInOut),
+            _ => None,
+        }
+    }
+}
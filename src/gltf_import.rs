@@ -0,0 +1,201 @@
+use crate::mesh::Mesh;
+
+/// A single drawable part of a glTF mesh, with the parts of its material we support
+pub struct Primitive {
+    pub mesh: Mesh,
+    pub base_color: glm::Vec4,
+    pub texture: Option<usize>, // Index into Model::images
+}
+
+/// A node of the glTF hierarchy, with its transformation converted to the
+/// conventions of SceneNode
+pub struct ModelNode {
+    pub name: String,
+    pub position: glm::Vec3,
+    pub rotation: glm::Vec3,
+    pub scale: glm::Vec3,
+    pub primitives: Vec<usize>, // Indices into Model::primitives
+    pub children: Vec<usize>,   // Indices into Model::nodes
+}
+
+/// Everything needed to build a scene graph out of a glTF file
+pub struct Model {
+    pub primitives: Vec<Primitive>,
+    pub images: Vec<Option<image::RgbaImage>>,
+    pub nodes: Vec<ModelNode>,
+    pub roots: Vec<usize>,
+}
+
+/// Finds the angles (x, y, z) for which Rx * Ry * Rz, as used by SceneNode, equals
+/// the given rotation
+fn euler_from_quat(rotation: [f32; 4]) -> glm::Vec3 {
+    let [x, y, z, w] = rotation;
+    let m = glm::quat_to_mat3(&glm::quat(x, y, z, w));
+
+    let sin_y = m[(0, 2)].clamp(-1.0, 1.0);
+    if sin_y.abs() < 0.9999 {
+        glm::vec3(
+            (-m[(1, 2)]).atan2(m[(2, 2)]),
+            sin_y.asin(),
+            (-m[(0, 1)]).atan2(m[(0, 0)]),
+        )
+    } else {
+        // Gimbal lock, x and z rotate about the same axis so put it all in x
+        glm::vec3(m[(2, 1)].atan2(m[(1, 1)]), sin_y.asin(), 0.0)
+    }
+}
+
+fn convert_image(data: &gltf::image::Data) -> Option<image::RgbaImage> {
+    use gltf::image::Format;
+
+    let pixels: Vec<u8> = match data.format {
+        Format::R8G8B8A8 => data.pixels.clone(),
+        Format::R8G8B8 => data
+            .pixels
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        Format::R8 => data.pixels.iter().flat_map(|&p| [p, p, p, 255]).collect(),
+        _ => return None,
+    };
+
+    // glTF puts the first row at the top, just like its texture coordinates, so there
+    // is no need to flip anything
+    image::RgbaImage::from_raw(data.width, data.height, pixels)
+}
+
+fn convert_primitive(
+    primitive: &gltf::Primitive,
+    buffers: &[gltf::buffer::Data],
+) -> Option<Primitive> {
+    if primitive.mode() != gltf::mesh::Mode::Triangles {
+        println!(
+            "Skipping primitive with unsupported mode {:?}",
+            primitive.mode()
+        );
+        return None;
+    }
+
+    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+    let vertices: Vec<f32> = reader.read_positions()?.flatten().collect();
+    let indices: Vec<u32> = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect(),
+        None => (0..(vertices.len() / 3) as u32).collect(),
+    };
+
+    let mut mesh = Mesh::new(vertices, indices);
+    if let Some(normals) = reader.read_normals() {
+        mesh.normals = normals.flatten().collect();
+    }
+    if let Some(texcoords) = reader.read_tex_coords(0) {
+        mesh.texcoords = texcoords.into_f32().flatten().collect();
+    }
+    if let Some(colors) = reader.read_colors(0) {
+        mesh.colors = colors.into_rgba_f32().flatten().collect();
+    }
+    mesh.generate_missing_attributes();
+
+    let material = primitive.material().pbr_metallic_roughness();
+    Some(Primitive {
+        mesh,
+        base_color: glm::Vec4::from(material.base_color_factor()),
+        texture: material
+            .base_color_texture()
+            .map(|info| info.texture().source().index()),
+    })
+}
+
+/// Loads a .gltf or .glb file, keeping the node hierarchy of its default scene
+pub fn load(path: &str) -> Model {
+    println!("Loading glTF model...");
+    let before = std::time::Instant::now();
+    let (document, buffers, images) =
+        gltf::import(path).unwrap_or_else(|e| panic!("Failed to load glTF model {}: {}", path, e));
+
+    // Convert every mesh, remembering which primitives each of them ended up as
+    let mut primitives: Vec<Primitive> = Vec::new();
+    let mesh_primitives: Vec<Vec<usize>> = document
+        .meshes()
+        .map(|mesh| {
+            mesh.primitives()
+                .filter_map(|primitive| convert_primitive(&primitive, &buffers))
+                .map(|primitive| {
+                    primitives.push(primitive);
+                    primitives.len() - 1
+                })
+                .collect()
+        })
+        .collect();
+
+    let nodes: Vec<ModelNode> = document
+        .nodes()
+        .map(|node| {
+            let (translation, rotation, scale) = node.transform().decomposed();
+            ModelNode {
+                name: node.name().unwrap_or("").to_string(),
+                position: glm::Vec3::from(translation),
+                rotation: euler_from_quat(rotation),
+                scale: glm::Vec3::from(scale),
+                primitives: node
+                    .mesh()
+                    .map(|mesh| mesh_primitives[mesh.index()].clone())
+                    .unwrap_or_default(),
+                children: node.children().map(|child| child.index()).collect(),
+            }
+        })
+        .collect();
+
+    let roots = match document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+    {
+        Some(scene) => scene.nodes().map(|node| node.index()).collect(),
+        None => Vec::new(),
+    };
+
+    let after = std::time::Instant::now();
+    println!(
+        "Done in {:.3}ms. Loaded {} nodes and {} primitives.",
+        after.duration_since(before).as_micros() as f32 / 1e3,
+        nodes.len(),
+        primitives.len(),
+    );
+
+    Model {
+        primitives,
+        images: images.iter().map(convert_image).collect(),
+        nodes,
+        roots,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    /// Quaternion in glTF order (x, y, z, w) rotating like Rx * Ry * Rz
+    fn quat_from_euler(angles: glm::Vec3) -> [f32; 4] {
+        let axis = |angle: f32, x, y, z| glm::quat_angle_axis(angle, &glm::vec3(x, y, z));
+        let q = axis(angles.x, 1.0, 0.0, 0.0)
+            * axis(angles.y, 0.0, 1.0, 0.0)
+            * axis(angles.z, 0.0, 0.0, 1.0);
+        [q.coords.x, q.coords.y, q.coords.z, q.coords.w]
+    }
+
+    #[test]
+    fn euler_round_trip() {
+        for &angles in &[
+            glm::vec3(0.0, 0.0, 0.0),
+            glm::vec3(0.3, -0.7, 1.2),
+            glm::vec3(-2.5, 1.1, -0.4),
+        ] {
+            let result = euler_from_quat(quat_from_euler(angles));
+            assert!(glm::distance(&result, &angles) < 1e-4, "{:?}", result);
+        }
+
+        // At gimbal lock only the sum of x and z matters, and it ends up in x
+        let result = euler_from_quat(quat_from_euler(glm::vec3(0.2, PI / 2.0, 0.3)));
+        assert!(glm::distance(&result, &glm::vec3(0.5, PI / 2.0, 0.0)) < 1e-3);
+    }
+}
@@ -0,0 +1,203 @@
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+use crate::mesh::Mesh;
+
+/// Colors from the lowest to the highest points of the terrain, blended by height
+const HEIGHT_COLORS: [[f32; 4]; 4] = [
+    [0.22, 0.20, 0.20, 1.0],
+    [0.42, 0.40, 0.38, 1.0],
+    [0.62, 0.60, 0.57, 1.0],
+    [0.90, 0.89, 0.87, 1.0],
+];
+
+/// Parameters of fractal noise. Each octave adds detail at `lacunarity` times the
+/// frequency and `persistence` times the amplitude of the one before
+#[derive(Clone, Copy, Debug)]
+pub struct NoiseSettings {
+    pub seed: u64,
+    pub octaves: u32,
+    pub frequency: f32, // Features per side of the terrain in the first octave
+    pub lacunarity: f32,
+    pub persistence: f32,
+}
+
+impl Default for NoiseSettings {
+    fn default() -> Self {
+        NoiseSettings {
+            seed: 0,
+            octaves: 6,
+            frequency: 4.0,
+            lacunarity: 2.0,
+            persistence: 0.5,
+        }
+    }
+}
+
+/// Gradient noise with a seeded permutation table, repeating every 256 units
+struct GradientNoise {
+    permutation: Vec<u8>,
+}
+
+impl GradientNoise {
+    fn new(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut permutation: Vec<u8> = (0..=255).collect();
+        permutation.shuffle(&mut rng);
+        GradientNoise { permutation }
+    }
+
+    fn gradient(&self, x: i32, y: i32) -> glm::Vec2 {
+        let hash =
+            self.permutation[((self.permutation[(x & 255) as usize] as i32 + y) & 255) as usize];
+        let angle = hash as f32 / 256.0 * std::f32::consts::PI * 2.0;
+        glm::vec2(angle.cos(), angle.sin())
+    }
+
+    /// Noise at the given point, roughly in -1..1
+    fn sample(&self, x: f32, y: f32) -> f32 {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i32, y0 as i32);
+
+        let corner = |dx: i32, dy: i32| {
+            let offset = glm::vec2(fx - dx as f32, fy - dy as f32);
+            glm::dot(&self.gradient(x0 + dx, y0 + dy), &offset)
+        };
+        // Quintic fade, so the normals come out smooth as well
+        let fade = |t: f32| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+        let (u, v) = (fade(fx), fade(fy));
+
+        let bottom = corner(0, 0) + (corner(1, 0) - corner(0, 0)) * u;
+        let top = corner(0, 1) + (corner(1, 1) - corner(0, 1)) * u;
+        (bottom + (top - bottom) * v) * std::f32::consts::SQRT_2
+    }
+}
+
+/// Blends between HEIGHT_COLORS by the given height, from 0 to 1
+fn height_color(height: f32) -> [f32; 4] {
+    let position = height.clamp(0.0, 1.0) * (HEIGHT_COLORS.len() - 1) as f32;
+    let band = (position as usize).min(HEIGHT_COLORS.len() - 2);
+    let t = position - band as f32;
+    let (low, high) = (HEIGHT_COLORS[band], HEIGHT_COLORS[band + 1]);
+    [
+        low[0] + (high[0] - low[0]) * t,
+        low[1] + (high[1] - low[1]) * t,
+        low[2] + (high[2] - low[2]) * t,
+        1.0,
+    ]
+}
+
+/// Makes a grid mesh out of heights from 0 to 1, given row by row. The grid is `size`
+/// wide along x and z, centered on the origin, and `height` tall
+fn make_grid(heights: &[f32], columns: u32, rows: u32, size: f32, height: f32) -> Mesh {
+    let (columns, rows) = (columns as usize, rows as usize);
+    let mut vertices = Vec::with_capacity(columns * rows * 3);
+    let mut texcoords = Vec::with_capacity(columns * rows * 2);
+    let mut colors = Vec::with_capacity(columns * rows * 4);
+    // Keep the cells square, even if the heightmap is not
+    let spacing = size / (columns.max(rows) - 1) as f32;
+
+    for row in 0..rows {
+        for column in 0..columns {
+            let h = heights[row * columns + column];
+            vertices.extend_from_slice(&[
+                (column as f32 - (columns - 1) as f32 / 2.0) * spacing,
+                h * height,
+                (row as f32 - (rows - 1) as f32 / 2.0) * spacing,
+            ]);
+            texcoords.extend_from_slice(&[
+                column as f32 / (columns - 1) as f32,
+                row as f32 / (rows - 1) as f32,
+            ]);
+            colors.extend_from_slice(&height_color(h));
+        }
+    }
+
+    let mut indices = Vec::with_capacity((columns - 1) * (rows - 1) * 6);
+    for row in 0..rows - 1 {
+        for column in 0..columns - 1 {
+            let a = (row * columns + column) as u32;
+            let b = a + 1;
+            let c = a + columns as u32;
+            let d = c + 1;
+            // Counter clockwise when seen from above
+            indices.extend_from_slice(&[a, c, b, b, c, d]);
+        }
+    }
+
+    let mut mesh = Mesh::new(vertices, indices);
+    mesh.colors = colors;
+    mesh.texcoords = texcoords;
+    mesh.generate_missing_attributes();
+    mesh
+}
+
+/// Makes terrain out of a grayscale heightmap image, where white is `height` above black.
+/// The image is stretched to be `size` wide along its longest side
+#[allow(dead_code)]
+pub fn from_image(path: &str, size: f32, height: f32) -> Mesh {
+    println!("Loading heightmap...");
+    let before = std::time::Instant::now();
+    let image = image::open(path)
+        .unwrap_or_else(|e| panic!("Failed to load heightmap {}: {}", path, e))
+        .into_luma16();
+    let (columns, rows) = image.dimensions();
+    assert!(
+        columns >= 2 && rows >= 2,
+        "Heightmap {} needs at least 2x2 pixels",
+        path
+    );
+
+    let heights: Vec<f32> = image.pixels().map(|p| p.0[0] as f32 / 65535.0).collect();
+    let mesh = make_grid(&heights, columns, rows, size, height);
+
+    let after = std::time::Instant::now();
+    println!(
+        "Done in {:.3}ms.",
+        after.duration_since(before).as_micros() as f32 / 1e3
+    );
+    mesh
+}
+
+/// Makes terrain out of fractal noise, with `resolution` vertices along each side. The
+/// same settings always give the same terrain
+pub fn from_noise(resolution: u32, size: f32, height: f32, settings: &NoiseSettings) -> Mesh {
+    println!("Generating terrain...");
+    let before = std::time::Instant::now();
+    let resolution = resolution.max(2);
+    let noise = GradientNoise::new(settings.seed);
+
+    let mut heights: Vec<f32> = Vec::with_capacity((resolution * resolution) as usize);
+    for row in 0..resolution {
+        for column in 0..resolution {
+            let (x, y) = (
+                column as f32 / (resolution - 1) as f32,
+                row as f32 / (resolution - 1) as f32,
+            );
+            let (mut sum, mut amplitude, mut frequency) = (0.0, 1.0, settings.frequency);
+            for _ in 0..settings.octaves {
+                sum += noise.sample(x * frequency, y * frequency) * amplitude;
+                amplitude *= settings.persistence;
+                frequency *= settings.lacunarity;
+            }
+            heights.push(sum);
+        }
+    }
+
+    // Stretch the heights to 0..1, so `height` is how tall the terrain actually gets
+    let lowest = heights.iter().cloned().fold(f32::INFINITY, f32::min);
+    let highest = heights.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+    let range = (highest - lowest).max(f32::EPSILON);
+    for h in heights.iter_mut() {
+        *h = (*h - lowest) / range;
+    }
+    let mesh = make_grid(&heights, resolution, resolution, size, height);
+
+    let after = std::time::Instant::now();
+    println!(
+        "Done in {:.3}ms.",
+        after.duration_since(before).as_micros() as f32 / 1e3
+    );
+    mesh
+}
@@ -1,5 +1,6 @@
 extern crate nalgebra_glm as glm;
 
+use std::collections::HashMap;
 use std::ptr;
 use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
@@ -7,20 +8,37 @@ use std::thread;
 use self::camera::Camera;
 use self::scene_graph::SceneNode;
 
+mod animation;
+mod articulation;
 mod camera;
+mod camera_path;
+mod capture;
 mod colors;
+mod easing;
+mod gltf_import;
+mod heightmap;
+mod material;
 mod mesh;
+mod obj;
+mod optimize;
+mod ply;
+mod rotor;
 mod scene_graph;
 mod shader;
 mod shapes;
+mod simplify;
+mod steering;
+mod stl;
 mod tasks;
+mod terrain;
+mod texture;
 mod toolbox;
 mod util;
 
 use glutin::event::{
     DeviceEvent,
     ElementState::{Pressed, Released},
-    Event, KeyboardInput,
+    Event, KeyboardInput, MouseButton, MouseScrollDelta,
     VirtualKeyCode::{self, *},
     WindowEvent,
 };
@@ -30,6 +48,84 @@ use glutin::event_loop::ControlFlow;
 const SCREEN_W: u32 = 800;
 const SCREEN_H: u32 = 600;
 const HELICOPTER_COUNT: u32 = 5;
+// Use a reversed, infinite depth range, so the far away terrain doesn't z-fight.
+// Needs OpenGL 4.5 or ARB_clip_control
+const REVERSED_Z: bool = false;
+// How far away the orbit camera starts from what it looks at
+const ORBIT_DISTANCE: f32 = 50.0;
+// Where the chase camera sits and what it looks at, behind the helicopter or in its
+// cockpit. Given in the helicopter's own frame, where the nose points towards -z
+const CHASE_VIEWS: [([f32; 3], [f32; 3]); 2] = [
+    ([0.0, 6.0, 25.0], [0.0, 2.0, -10.0]),
+    ([0.0, 1.8, -1.5], [0.0, 1.5, -20.0]),
+];
+// Camera path that K adds keys to and P plays back. Keys are added this many seconds
+// after the last one
+const CAMERA_PATH_FILE: &str = "resources/flythrough.path";
+const CAMERA_PATH_KEY_INTERVAL: f32 = 2.0;
+// Step path playback this many seconds per frame instead of following the clock.
+// Captures started with --capture <directory> always step at CAPTURE_FRAME_RATE, so
+// they come out the same every time
+const PATH_FIXED_TIMESTEP: Option<f32> = None;
+const CAPTURE_FRAME_RATE: f32 = 30.0;
+// How fast the rotors spin in radians per second at full throttle, and how quickly
+// they speed up and slow down. Landed helicopters keep the rotors going at IDLE_THROTTLE
+const MAIN_ROTOR: rotor::RotorSettings = rotor::RotorSettings {
+    max_speed: 10.0,
+    spin_up: 3.0,
+    spin_down: 1.5,
+};
+const TAIL_ROTOR: rotor::RotorSettings = rotor::RotorSettings {
+    max_speed: 15.0,
+    spin_up: 4.5,
+    spin_down: 2.25,
+};
+const IDLE_THROTTLE: f32 = 0.3;
+// Height the helicopters fly at along their flight paths. They land on the terrain
+const FLIGHT_ALTITUDE: f32 = 0.0;
+// Size of the circle and the wandering area some of the helicopters fly around, and
+// the loop the others fly through one corner at a time
+const FLIGHT_RADIUS: f64 = 35.0;
+const FLIGHT_WAYPOINTS: [[f64; 2]; 5] = [
+    [-40.0, -30.0],
+    [-10.0, -45.0],
+    [35.0, -25.0],
+    [30.0, 20.0],
+    [-25.0, 30.0],
+];
+// How far the doors slide back along the helicopter when open, and how long it takes
+const DOOR_OPEN_OFFSET: [f32; 3] = [0.0, 0.0, 2.0];
+const DOOR_DURATION: f32 = 1.2;
+// How high above the terrain the flock aims to fly, and how much each press of + or -
+// changes the steering weight being tuned
+const FLOCK_ALTITUDE: f32 = 25.0;
+const STEERING_TUNING_STEP: f32 = 1.25;
+// Reorder the terrain for better vertex cache use and less overdraw after loading
+const OPTIMIZE_MESHES: bool = true;
+// Screen heights below which helicopters and terrain chunks switch to simpler meshes,
+// each level having LOD_RATIO times the triangles of the one before
+const LOD_SCREEN_SIZES: [f32; 3] = [0.15, 0.06, 0.02];
+const LOD_RATIO: f32 = 0.4;
+// Most triangles in a single terrain chunk
+const TERRAIN_CHUNK_TRIANGLES: usize = 4096;
+// Terrain chunks this close to the camera along the ground get all their triangles
+// uploaded, which are let go again once they are further away than the evict distance.
+// Chunks without them are drawn by their simplest level of detail
+const TERRAIN_LOAD_DISTANCE: f32 = 250.0;
+const TERRAIN_EVICT_DISTANCE: f32 = 300.0;
+// Generate the terrain from noise with these settings, instead of loading the lunar
+// surface. The terrain is PROCEDURAL_TERRAIN_SIZE wide with that many vertices per side
+const PROCEDURAL_TERRAIN: Option<heightmap::NoiseSettings> = None;
+const PROCEDURAL_TERRAIN_SIZE: f32 = 600.0;
+const PROCEDURAL_TERRAIN_HEIGHT: f32 = 60.0;
+const PROCEDURAL_TERRAIN_RESOLUTION: u32 = 257;
+// Load this glTF model as well, placing it on the terrain at GLTF_MODEL_POSITION
+const GLTF_MODEL: Option<&str> = None;
+const GLTF_MODEL_POSITION: [f32; 3] = [0.0, 0.0, 0.0];
+// Where X exports the scene as it currently looks, both as OBJ with a material per
+// part and as a single vertex colored PLY
+const EXPORT_OBJ_FILE: &str = "resources/export.obj";
+const EXPORT_PLY_FILE: &str = "resources/export.ply";
 
 /// Makes a new buffer and fills it with the given data values. Leaves the created
 /// buffer bound
@@ -50,6 +146,19 @@ unsafe fn make_buffer<T>(target: gl::types::GLenum, values: &Vec<T>) -> u32 {
     buffer_id
 }
 
+/// Makes a new buffer for the vertex attribute at the given location and enables it.
+/// Empty attribute streams are left disabled, making OpenGL use a constant value instead
+unsafe fn make_attribute_buffer(location: u32, components: i32, values: &Vec<f32>) {
+    if values.is_empty() {
+        gl::DisableVertexAttribArray(location);
+        return;
+    }
+
+    make_buffer(gl::ARRAY_BUFFER, values);
+    gl::VertexAttribPointer(location, components, gl::FLOAT, gl::FALSE, 0, ptr::null());
+    gl::EnableVertexAttribArray(location);
+}
+
 /// Makes a new VAO, feeds the vertices to a new VBO for said VAO and makes an index buffer
 /// with the given indices
 unsafe fn make_vao(
@@ -63,27 +172,61 @@ unsafe fn make_vao(
     gl::GenVertexArrays(1, &mut id as *mut u32);
     gl::BindVertexArray(id);
 
-    // Make and fill buffer. This function leaves the created VBO bound
-    make_buffer(gl::ARRAY_BUFFER, vertices);
-    gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, 0, ptr::null());
+    // Make and fill buffers. These are left bound to the VAO
+    make_attribute_buffer(0, 3, vertices);
+    make_attribute_buffer(1, 4, colors);
+    make_attribute_buffer(2, 3, normals);
+
+    make_buffer(gl::ELEMENT_ARRAY_BUFFER, indices);
 
-    make_buffer(gl::ARRAY_BUFFER, colors);
-    gl::VertexAttribPointer(1, 4, gl::FLOAT, gl::FALSE, 0, ptr::null());
+    id
+}
 
-    make_buffer(gl::ARRAY_BUFFER, normals);
-    gl::VertexAttribPointer(2, 3, gl::FLOAT, gl::FALSE, 0, ptr::null());
+unsafe fn make_mesh_vao(mesh: &mesh::Mesh) -> u32 {
+    // Catch broken meshes here, rather than deep inside some OpenGL call
+    if let Err(issues) = mesh.validate() {
+        for issue in &issues {
+            println!("Mesh issue: {}", issue);
+        }
+        if issues.iter().any(|issue| issue.is_fatal()) {
+            panic!("Refusing to upload broken mesh, try Mesh::repair");
+        }
+    }
 
-    gl::EnableVertexAttribArray(0);
-    gl::EnableVertexAttribArray(1);
-    gl::EnableVertexAttribArray(2);
+    let id = make_vao(&mesh.vertices, &mesh.indices, &mesh.colors, &mesh.normals);
 
-    make_buffer(gl::ELEMENT_ARRAY_BUFFER, indices);
+    // The VAO is still bound, so we can keep adding attributes to it
+    make_attribute_buffer(3, 4, &mesh.tangents);
+    make_attribute_buffer(4, 2, &mesh.texcoords);
 
     id
 }
 
-unsafe fn make_mesh_vao(mesh: &mesh::Mesh) -> u32 {
-    make_vao(&mesh.vertices, &mesh.indices, &mesh.colors, &mesh.normals)
+/// Makes VAOs for increasingly simple versions of the given mesh, for drawing it when
+/// it is far away
+fn make_lods(mesh: &mesh::Mesh) -> Vec<scene_graph::LodLevel> {
+    simplify::lod_chain(mesh, LOD_SCREEN_SIZES.len(), LOD_RATIO)
+        .iter()
+        .zip(LOD_SCREEN_SIZES.iter())
+        .map(|(lod, &screen_size)| scene_graph::LodLevel {
+            vao_id: unsafe { make_mesh_vao(lod) },
+            index_count: lod.index_count,
+            screen_size,
+        })
+        .collect()
+}
+
+/// Makes a scene node drawing the given mesh, or one of its levels of detail
+fn make_lod_node(
+    mesh: &mesh::Mesh,
+    vao_id: u32,
+    lods: &[scene_graph::LodLevel],
+    shader_id: u32,
+) -> scene_graph::Node {
+    let mut node = SceneNode::from_vao(vao_id, shader_id, mesh.index_count);
+    node.bounding_sphere = mesh.bounding_sphere();
+    node.lods = lods.to_vec();
+    node
 }
 
 unsafe fn draw_mesh_vao(
@@ -92,6 +235,7 @@ unsafe fn draw_mesh_vao(
     total_transform: &glm::Mat4,
     model_transform: &glm::Mat4,
     shader_id: &u32,
+    material: &material::Material,
 ) {
     // Make sure the VAO is selected before we call draw
     gl::BindVertexArray(*vao_id);
@@ -101,6 +245,7 @@ unsafe fn draw_mesh_vao(
 
     gl::UniformMatrix4fv(3, 1, gl::FALSE, total_transform.as_ptr());
     gl::UniformMatrix4fv(4, 1, gl::FALSE, model_transform.as_ptr());
+    material.apply();
     // Draw scene
     gl::DrawElements(gl::TRIANGLES, *index_count, gl::UNSIGNED_INT, ptr::null());
 }
@@ -108,12 +253,20 @@ unsafe fn draw_mesh_vao(
 /// Draws the given node and all children using the given view transform
 unsafe fn draw_scene(node: &SceneNode, view_transform: &glm::Mat4) {
     if node.vao_id > 0 {
+        let (vao_id, index_count) = if node.lods.is_empty() {
+            (node.vao_id, node.index_count)
+        } else {
+            let (center, radius) = node.world_bounding_sphere();
+            node.select_lod(camera::projected_size(view_transform, &center, radius))
+        };
+
         draw_mesh_vao(
-            &node.vao_id,
-            &node.index_count,
+            &vao_id,
+            &index_count,
             &(view_transform * node.current_transformation_matrix),
             &node.current_transformation_matrix,
             &node.shader_id,
+            &node.material,
         );
     }
 
@@ -122,10 +275,74 @@ unsafe fn draw_scene(node: &SceneNode, view_transform: &glm::Mat4) {
     }
 }
 
+/// Uploads the triangles of chunks that became resident, and deletes those of chunks
+/// that were evicted. The vertices are shared by all chunks, so only indices are moved
+unsafe fn update_terrain_buffers(
+    terrain: &mut terrain::ChunkedTerrain,
+    changes: &terrain::ResidencyChanges,
+) {
+    // Keep the new index buffers from being attached to whatever VAO is bound
+    gl::BindVertexArray(0);
+    for &index in &changes.loaded {
+        let indices = terrain.mesh.indices[terrain.chunks[index].indices()].to_vec();
+        terrain.chunks[index].index_buffer_id = make_buffer(gl::ELEMENT_ARRAY_BUFFER, &indices);
+    }
+    for &index in &changes.evicted {
+        let chunk = &mut terrain.chunks[index];
+        gl::DeleteBuffers(1, &chunk.index_buffer_id);
+        chunk.index_buffer_id = 0;
+    }
+}
+
+/// Draws the chunks of the terrain which are in view, using the transform, shader and
+/// material of the given node. Resident chunks close enough to need every triangle draw
+/// their own index buffer with the shared vertices, the others a level of detail
+unsafe fn draw_terrain(
+    terrain: &terrain::ChunkedTerrain,
+    node: &SceneNode,
+    view_transform: &glm::Mat4,
+) {
+    let total_transform = view_transform * node.current_transformation_matrix;
+    gl::UseProgram(node.shader_id);
+    gl::UniformMatrix4fv(3, 1, gl::FALSE, total_transform.as_ptr());
+    gl::UniformMatrix4fv(4, 1, gl::FALSE, node.current_transformation_matrix.as_ptr());
+    node.material.apply();
+
+    for index in terrain.visible_chunks(&total_transform) {
+        let chunk = &terrain.chunks[index];
+        // The bounds are in the terrain's own space, which the total transform starts from
+        let (center, radius) = chunk.bounding_sphere();
+        let screen_size = camera::projected_size(&total_transform, &center, radius);
+        let lod = match scene_graph::select_lod(&chunk.lods, screen_size) {
+            None if chunk.index_buffer_id == 0 => chunk.lods.last(),
+            lod => lod,
+        };
+        match lod {
+            Some(lod) => {
+                gl::BindVertexArray(lod.vao_id);
+                gl::DrawElements(gl::TRIANGLES, lod.index_count, gl::UNSIGNED_INT, ptr::null());
+            }
+            None if chunk.index_buffer_id != 0 => {
+                gl::BindVertexArray(terrain.vao_id);
+                gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, chunk.index_buffer_id);
+                gl::DrawElements(gl::TRIANGLES, chunk.index_count, gl::UNSIGNED_INT, ptr::null());
+            }
+            None => {}
+        }
+    }
+}
+
+/// Directory given with --capture on the command line, if any
+fn capture_directory_argument() -> Option<String> {
+    let mut args = std::env::args();
+    args.position(|arg| arg == "--capture")?;
+    Some(args.next().expect("--capture needs a directory to save the frames in"))
+}
+
 /// Updates all node local transformations
 unsafe fn update_node_transformations(node: &mut SceneNode, initial_transform: &glm::Mat4) {
-    // Construct transformation matrix
-    let mut transform = glm::Mat4::identity();
+    // Construct transformation matrix. Scale first, so it doesn't affect the position
+    let mut transform = glm::scaling(&node.scale);
     // First translate by (subtract) reference point, then rotate, then translate back
     transform = glm::translation(&node.reference_point)
         * glm::rotation(node.rotation.x, &glm::vec3(1.0, 0.0, 0.0))
@@ -134,7 +351,6 @@ unsafe fn update_node_transformations(node: &mut SceneNode, initial_transform: &
         * glm::translation(&-node.reference_point)
         * transform;
     transform = glm::translation(&node.position) * transform;
-    transform = glm::scaling(&node.scale) * transform;
 
     node.current_transformation_matrix = initial_transform * transform;
 
@@ -145,42 +361,110 @@ unsafe fn update_node_transformations(node: &mut SceneNode, initial_transform: &
 
 /// Utility struct for keeping track of a helicopter model instance
 struct Helicopter {
-    id: u32,
     body: scene_graph::Node,
     main_rotor: scene_graph::Node,
     tail_rotor: scene_graph::Node,
     door: scene_graph::Node,
+    door_state: articulation::Articulation,
+    main_rotor_state: rotor::Rotor,
+    tail_rotor_state: rotor::Rotor,
+    throttle: f32,  // 1 to fly, IDLE_THROTTLE to land and idle, 0 to stop the rotors
+    flight_path: toolbox::FlightPath,
+    flight: animation::AnimationPlayer, // Plays the flight path on the body
 }
 
 impl Helicopter {
-    /// The time for this specific helicopter, offset to compensate
-    /// for collisions between multiple helicopters in the scene
-    fn get_time_with_offset(&mut self, time: f32, offset: f32) -> f32 {
-        return time + (offset * self.id as f32);
+    /// Spins the rotors up or down towards what the throttle asks for
+    fn update_rotors(&mut self, delta_time: f32) {
+        self.main_rotor_state.update(self.throttle, delta_time);
+        self.tail_rotor_state.update(self.throttle, delta_time);
+        self.main_rotor.rotation.y = self.main_rotor_state.angle;
+        self.tail_rotor.rotation.x = self.tail_rotor_state.angle;
+    }
+
+    /// Moves along the flight path as fast as the main rotor lifts the helicopter, so it
+    /// sinks down to the ground and stops there as the rotor slows, and takes off again
+    /// once it is back up to speed
+    fn update_heading<H>(&mut self, delta_time: f32, ground_height: H)
+    where
+        H: Fn(f32, f32) -> Option<f32>,
+    {
+        let lift = self.main_rotor_state.lift();
+        self.flight.speed = lift;
+        self.flight.advance(delta_time);
+
+        if lift >= 1.0 {
+            return;
+        }
+        // Sink towards the ground as the lift drops, staying on top of it where it rises
+        // above the flight path
+        let position = &mut self.body.position;
+        if let Some(ground) = ground_height(position.x, position.z) {
+            position.y = ground.max(ground + (position.y - ground) * lift);
+        }
+        // Level out on the ground
+        self.body.rotation.x *= lift;
+        self.body.rotation.z *= lift;
     }
 
-    /// Updates rotor values to have spinning animation
-    fn update_rotors(&mut self, time: f32, offset: f32) {
-        let elapsed = self.get_time_with_offset(time, offset);
+    /// Slides the door towards wherever its state machine is headed
+    fn update_door(&mut self, delta_time: f32) {
+        self.door_state.update(delta_time);
+        self.door_state.apply(&mut self.door);
+    }
 
-        self.main_rotor.rotation.y = elapsed * 10.0;
-        self.tail_rotor.rotation.x = elapsed * 15.0;
+    /// Places the helicopter where its agent in the flock is, leaning the way it would
+    /// along its flight path
+    fn follow_agent(&mut self, agent: &steering::Agent) {
+        self.body.position = agent.position;
+        let velocity = glm::vec2(agent.velocity.x as f64, agent.velocity.z as f64);
+        // Keep facing the same way when hovering in place
+        if glm::length(&velocity) < 1e-3 {
+            return;
+        }
+        let acceleration = glm::vec2(agent.acceleration.x as f64, agent.acceleration.z as f64);
+        let (roll, pitch, yaw) = self.flight_path.lean(velocity, acceleration);
+        self.body.rotation = glm::vec3(pitch as f32, yaw as f32, roll as f32);
     }
+}
 
-    /// Updates heading for this helicopter's animation
-    fn update_heading(&mut self, time: f32, offset: f32) {
-        let heading = toolbox::simple_heading_animation(self.get_time_with_offset(time, offset));
-        self.body.position.x = heading.x;
-        self.body.position.z = heading.z;
-        self.body.rotation.x = heading.pitch;
-        self.body.rotation.y = heading.yaw;
-        self.body.rotation.z = heading.roll;
+/// Flight path of the helicopter with the given index. Goes through the figure-eight,
+/// a circle, the waypoints and wandering around in turn
+fn make_flight_path(index: u32) -> toolbox::FlightPath {
+    match index % 4 {
+        0 => toolbox::FlightPath::default(),
+        1 => toolbox::FlightPath::new(toolbox::FlightShape::Circle {
+            radius: FLIGHT_RADIUS,
+        }),
+        2 => toolbox::FlightPath::new(toolbox::FlightShape::Waypoints {
+            points: FLIGHT_WAYPOINTS.iter().map(|&[x, z]| glm::vec2(x, z)).collect(),
+        }),
+        _ => toolbox::FlightPath::wander(index as u64, FLIGHT_RADIUS),
     }
 }
 
+/// Materials showing the given diffuse textures, loading each texture file only once.
+/// Untextured parts get a plain material
+fn make_materials(textures: &[Option<String>]) -> Vec<material::Material> {
+    let mut loaded: HashMap<&str, material::Material> = HashMap::new();
+    textures
+        .iter()
+        .map(|texture| match texture {
+            Some(path) => *loaded.entry(path).or_insert_with(|| unsafe {
+                material::Material::with_texture(&texture::Texture2D::load(path))
+            }),
+            None => material::Material::new(),
+        })
+        .collect()
+}
+
 /// Makes VAOs for helicopter model and instanties a given amount of scene
-/// nodes using them
-fn make_helicopters(shader_id: u32, amount: u32) -> Vec<Helicopter> {
+/// nodes using them. The meshes are kept in `meshes` by VAO, for exporting
+fn make_helicopters(
+    shader_id: u32,
+    amount: u32,
+    meshes: &mut HashMap<u32, mesh::Mesh>,
+) -> Vec<Helicopter> {
     // Load meshes
     let model = mesh::Helicopter::load("resources/helicopter.obj");
 
@@ -190,47 +474,190 @@ fn make_helicopters(shader_id: u32, amount: u32) -> Vec<Helicopter> {
     let main_rot_vao_id = unsafe { make_mesh_vao(&model.main_rotor) };
     let tail_rot_vao_id = unsafe { make_mesh_vao(&model.tail_rotor) };
 
+    // Simpler versions of each part, shared by all helicopters
+    let body_lods = make_lods(&model.body);
+    let door_lods = make_lods(&model.door);
+    let main_rot_lods = make_lods(&model.main_rotor);
+    let tail_rot_lods = make_lods(&model.tail_rotor);
+    let materials = make_materials(&model.textures);
+
+    meshes.insert(body_vao_id, model.body.clone());
+    meshes.insert(door_vao_id, model.door.clone());
+    meshes.insert(main_rot_vao_id, model.main_rotor.clone());
+    meshes.insert(tail_rot_vao_id, model.tail_rotor.clone());
+
+    // Spread out the helicopters sharing a flight path, so they don't collide
+    let offset = 15.0 / amount as f32;
+
     (0..amount)
         .map(|idx| {
-            let mut root = SceneNode::from_vao(body_vao_id, shader_id, model.body.index_count);
+            let mut root = make_lod_node(&model.body, body_vao_id, &body_lods, shader_id);
 
-            let mut door = SceneNode::from_vao(door_vao_id, shader_id, model.door.index_count);
+            let mut door = make_lod_node(&model.door, door_vao_id, &door_lods, shader_id);
             // Guessing the reference point for this one (Little bit up and to the left)
             door.reference_point = glm::vec3(1.0, 1.5, 0.0);
 
             let mut main_rot =
-                SceneNode::from_vao(main_rot_vao_id, shader_id, model.main_rotor.index_count);
+                make_lod_node(&model.main_rotor, main_rot_vao_id, &main_rot_lods, shader_id);
             // This doesn't actually need a reference point for the rotation we wish to perform,
             // but say we want to implement some kind of "tilt" to animate how a helicopter
             // changes direction we could do that with a reference point at the center of the rotor
             // which this is pretty close to being
             main_rot.reference_point = glm::Vec3::new(0.0, 2.3, 0.0);
             let mut tail_rot =
-                SceneNode::from_vao(tail_rot_vao_id, shader_id, model.tail_rotor.index_count);
+                make_lod_node(&model.tail_rotor, tail_rot_vao_id, &tail_rot_lods, shader_id);
             tail_rot.reference_point = glm::Vec3::new(0.35, 2.3, 10.4);
 
+            // In the same order as indexing the parts of the model
+            root.material = materials[0];
+            main_rot.material = materials[1];
+            tail_rot.material = materials[2];
+            door.material = materials[3];
+
             // Add children to root node
             root.add_child(&door);
             root.add_child(&main_rot);
             root.add_child(&tail_rot);
 
+            let flight_path = make_flight_path(idx);
+            let mut flight = animation::AnimationPlayer::new();
+            flight.add(&mut root, flight_path.animation(FLIGHT_ALTITUDE), offset * idx as f32);
+
             Helicopter {
-                id: idx,
                 body: root,
                 door: door,
+                door_state: articulation::Articulation::new(
+                    animation::Target::Position,
+                    glm::zero(),
+                    DOOR_OPEN_OFFSET.into(),
+                    DOOR_DURATION,
+                ),
+                main_rotor_state: rotor::Rotor::new(MAIN_ROTOR),
+                tail_rotor_state: rotor::Rotor::new(TAIL_ROTOR),
+                throttle: 1.0,
                 main_rotor: main_rot,
                 tail_rotor: tail_rot,
+                flight_path,
+                flight,
             }
         })
         .collect()
 }
 
-/// Makes a scene graph with the lunar terrain and the given amount of helicopters
-fn make_scene_graph(helicopters: u32) -> (scene_graph::Node, Vec<Helicopter>) {
-    let terrain = mesh::Terrain::load("resources/lunarsurface.obj");
+/// Loads a glTF model and turns its node hierarchy into scene nodes, keeping the
+/// pivots and materials authored in the file. Returns the root along with every
+/// node of the file and its name, so parts of the model can be animated. The meshes
+/// are kept in `meshes` by VAO, for exporting
+fn make_gltf_scene(
+    path: &str,
+    shader_id: u32,
+    meshes: &mut HashMap<u32, mesh::Mesh>,
+) -> (scene_graph::Node, Vec<(String, scene_graph::Node)>) {
+    let model = gltf_import::load(path);
+
+    let textures: Vec<u32> = model
+        .images
+        .iter()
+        .map(|image| match image {
+            Some(image) => unsafe { texture::Texture2D::from_image(image, true).id },
+            None => 0,
+        })
+        .collect();
+    let vao_ids: Vec<u32> = model
+        .primitives
+        .iter()
+        .map(|primitive| unsafe { make_mesh_vao(&primitive.mesh) })
+        .collect();
+    for (&vao_id, primitive) in vao_ids.iter().zip(model.primitives.iter()) {
+        meshes.insert(vao_id, primitive.mesh.clone());
+    }
+
+    let make_primitive_node = |index: usize| {
+        let primitive = &model.primitives[index];
+        let mut node = SceneNode::from_vao(vao_ids[index], shader_id, primitive.mesh.index_count);
+        node.material = material::Material {
+            base_color: primitive.base_color,
+            texture_id: primitive.texture.map_or(0, |texture| textures[texture]),
+        };
+        node
+    };
 
-    // Set up terrain VAO and load shader
-    let terrain_vao_id = unsafe { make_mesh_vao(&terrain) };
+    let mut nodes: Vec<(String, scene_graph::Node)> = model
+        .nodes
+        .iter()
+        .map(|model_node| {
+            let mut node = match model_node.primitives.first() {
+                Some(&primitive) => make_primitive_node(primitive),
+                None => SceneNode::new(),
+            };
+            node.position = model_node.position;
+            node.rotation = model_node.rotation;
+            node.scale = model_node.scale;
+            (model_node.name.clone(), node)
+        })
+        .collect();
+
+    for (index, model_node) in model.nodes.iter().enumerate() {
+        // A scene node only draws a single VAO, so any further primitives become children
+        for &primitive in model_node.primitives.iter().skip(1) {
+            nodes[index].1.add_child(&make_primitive_node(primitive));
+        }
+
+        // Nodes can't be their own children, so the parent and child are always apart
+        for &child in &model_node.children {
+            let (parent, child) = if child < index {
+                let (before, after) = nodes.split_at_mut(index);
+                (&mut after[0].1, &before[child].1)
+            } else {
+                let (before, after) = nodes.split_at_mut(child);
+                (&mut before[index].1, &after[0].1)
+            };
+            parent.add_child(child);
+        }
+    }
+
+    let mut root = SceneNode::new();
+    for &index in &model.roots {
+        root.add_child(&nodes[index].1);
+    }
+    (root, nodes)
+}
+
+/// Makes a scene graph with the lunar terrain and the given amount of helicopters. The
+/// terrain is drawn in chunks rather than through its node, which only provides the
+/// transform, shader and material for them. Also returns the meshes drawn by each VAO
+/// in the graph, for exporting it
+fn make_scene_graph(
+    helicopters: u32,
+) -> (
+    scene_graph::Node,
+    Vec<Helicopter>,
+    terrain::ChunkedTerrain,
+    HashMap<u32, mesh::Mesh>,
+) {
+    let (terrain_mesh, terrain_texture) = match PROCEDURAL_TERRAIN {
+        Some(settings) => (
+            heightmap::from_noise(
+                PROCEDURAL_TERRAIN_RESOLUTION,
+                PROCEDURAL_TERRAIN_SIZE,
+                PROCEDURAL_TERRAIN_HEIGHT,
+                &settings,
+            ),
+            None,
+        ),
+        None => mesh::Terrain::load("resources/lunarsurface.obj"),
+    };
+    let mut terrain = terrain::ChunkedTerrain::new(&terrain_mesh, TERRAIN_CHUNK_TRIANGLES);
+    if OPTIMIZE_MESHES {
+        let report = terrain.optimize();
+        println!(
+            "Optimized terrain, ACMR went from {:.3} to {:.3}.",
+            report.acmr_before, report.acmr_after
+        );
+    }
+    println!("Split terrain into {} chunks.", terrain.chunks.len());
+
+    // Load shader
     let shader = unsafe {
         shader::ShaderBuilder::new()
             .attach_file("shaders/simple.vert")
@@ -238,36 +665,85 @@ fn make_scene_graph(helicopters: u32) -> (scene_graph::Node, Vec<Helicopter>) {
             .link()
     };
 
-    let mut terrain_node =
-        SceneNode::from_vao(terrain_vao_id, shader.program_id, terrain.index_count);
+    // Only the vertices are uploaded up front, the triangles follow as chunks get close
+    let vertices = mesh::Mesh {
+        indices: Vec::new(),
+        index_count: 0,
+        ..terrain.mesh.clone()
+    };
+    terrain.vao_id = unsafe { make_mesh_vao(&vertices) };
+    // Distant chunks are drawn simplified, just like the helicopters
+    for chunk in 0..terrain.chunks.len() {
+        terrain.chunks[chunk].lods = make_lods(&terrain.chunk_mesh(chunk));
+    }
+    let mut terrain_node = SceneNode::from_vao(0, shader.program_id, 0);
+    terrain_node.material = make_materials(&[terrain_texture])[0];
 
-    let helicopters = make_helicopters(shader.program_id, helicopters);
+    let mut meshes = HashMap::new();
+    let helicopters = make_helicopters(shader.program_id, helicopters, &mut meshes);
 
     // Add helicopters to the terrain
     for helicopter in &helicopters {
         terrain_node.add_child(&helicopter.body);
     }
 
+    if let Some(path) = GLTF_MODEL {
+        let (mut model, _) = make_gltf_scene(path, shader.program_id, &mut meshes);
+        model.position = glm::make_vec3(&GLTF_MODEL_POSITION);
+        terrain_node.add_child(&model);
+    }
+
     let mut root = SceneNode::new();
     root.add_child(&terrain_node);
-    (root, helicopters)
+    (root, helicopters, terrain, meshes)
+}
+
+/// Saves the scene as it currently looks to EXPORT_OBJ_FILE and EXPORT_PLY_FILE, with
+/// the transformations of the nodes baked into the vertices
+fn export_scene(
+    root: &SceneNode,
+    terrain: &terrain::ChunkedTerrain,
+    meshes: &HashMap<u32, mesh::Mesh>,
+) -> std::io::Result<()> {
+    let meshes: HashMap<u32, &mesh::Mesh> = meshes.iter().map(|(&id, mesh)| (id, mesh)).collect();
+
+    // The terrain isn't drawn through its node, so it's added separately
+    let terrain_node = &root[0];
+    let terrain_mesh = terrain
+        .mesh
+        .transformed(&terrain_node.current_transformation_matrix);
+
+    let mut parts = vec![(terrain_mesh.clone(), terrain_node.material)];
+    parts.extend(root.flatten(&meshes));
+    obj::save(EXPORT_OBJ_FILE, &parts)?;
+
+    let mut merged = terrain_mesh;
+    merged.append(&root.flatten_to_mesh(&meshes));
+    ply::save(EXPORT_PLY_FILE, &merged, false)
 }
 
 fn main() {
+    // With --capture <directory>, the camera path plays once without showing the
+    // window, every frame is saved in the directory, and the program quits afterwards
+    let capture_directory = capture_directory_argument();
+
     // Set up the necessary objects to deal with windows and event handling
     let el = glutin::event_loop::EventLoop::new();
     let wb = glutin::window::WindowBuilder::new()
         .with_title("Gloom-rs")
         .with_resizable(false)
+        .with_visible(capture_directory.is_none())
         .with_inner_size(glutin::dpi::LogicalSize::new(SCREEN_W, SCREEN_H));
     let cb = glutin::ContextBuilder::new().with_vsync(true);
     let windowed_context = cb.build_windowed(wb, &el).unwrap();
 
-    windowed_context
-        .window()
-        .set_cursor_grab(true)
-        .expect("failed to grab cursor");
-    windowed_context.window().set_cursor_visible(false);
+    if capture_directory.is_none() {
+        windowed_context
+            .window()
+            .set_cursor_grab(true)
+            .expect("failed to grab cursor");
+        windowed_context.window().set_cursor_visible(false);
+    }
 
     // Set up a shared vector for keeping track of currently pressed keys
     let arc_pressed_keys = Arc::new(Mutex::new(Vec::<VirtualKeyCode>::with_capacity(10)));
@@ -279,8 +755,17 @@ fn main() {
     // Make a reference of this tuple to send to the render thread
     let mouse_delta = Arc::clone(&arc_mouse_delta);
 
-    // Instantiate camera
+    // Same for held mouse buttons and lines scrolled since last frame
+    let arc_mouse_buttons = Arc::new(Mutex::new(Vec::<MouseButton>::with_capacity(3)));
+    let mouse_buttons = Arc::clone(&arc_mouse_buttons);
+    let arc_scroll_delta = Arc::new(Mutex::new(0f32));
+    let scroll_delta = Arc::clone(&arc_scroll_delta);
+
+    // Instantiate camera, matching the actual size of the window in pixels
     let mut camera = Camera::new();
+    let window_size = windowed_context.window().inner_size();
+    camera.set_viewport_size(window_size.width, window_size.height);
+    camera.reversed_z = REVERSED_Z;
 
     // Spawn a separate thread for rendering, so event handling doesn't block rendering
     let render_thread = thread::spawn(move || {
@@ -295,7 +780,14 @@ fn main() {
         // Set up openGL
         unsafe {
             gl::Enable(gl::DEPTH_TEST);
-            gl::DepthFunc(gl::LESS);
+            if camera.reversed_z {
+                // Depth goes from 1 at the near plane to 0 at infinity
+                gl::ClipControl(gl::LOWER_LEFT, gl::ZERO_TO_ONE);
+                gl::ClearDepth(0.0);
+                gl::DepthFunc(gl::GREATER);
+            } else {
+                gl::DepthFunc(gl::LESS);
+            }
             gl::Enable(gl::CULL_FACE);
             gl::Disable(gl::MULTISAMPLE);
             gl::Enable(gl::BLEND);
@@ -316,74 +808,394 @@ fn main() {
             );
         }
 
-        let first_frame_time = std::time::Instant::now();
-        let mut last_frame_time = first_frame_time;
-        let (mut root_node, mut helicopters) = make_scene_graph(HELICOPTER_COUNT);
+        let mut last_frame_time = std::time::Instant::now();
+        // Time the scene is animated by. Playing a camera path starts it over, so the
+        // helicopters are in the same place every time
+        let mut scene_time = 0.0f32;
+        let (mut root_node, mut helicopters, mut terrain, meshes) =
+            make_scene_graph(HELICOPTER_COUNT);
+
+        let mut previous_keys: Vec<VirtualKeyCode> = Vec::new();
+
+        // Orbiting either a fixed point or one of the helicopters, or chasing one of them
+        let mut controller = camera::Controller::FreeFly;
+        let mut orbit = camera::OrbitController::from_camera(&camera, ORBIT_DISTANCE);
+        let mut chase_view = 0;
+        let make_chase = |camera: &Camera, view: usize| {
+            let (offset, look_at) = CHASE_VIEWS[view];
+            camera::ChaseController::from_camera(camera, offset.into(), look_at.into())
+        };
+        let mut chase = make_chase(&camera, chase_view);
+        let mut selected_helicopter: Option<usize> = None;
+
+        // Pick up the path recorded last time, if there is one
+        let mut camera_path = if std::path::Path::new(CAMERA_PATH_FILE).exists() {
+            camera_path::CameraPath::load(CAMERA_PATH_FILE).unwrap_or_else(|e| {
+                println!("Failed to load camera path {}", e);
+                camera_path::CameraPath::new(camera_path::Spline::CatmullRom)
+            })
+        } else {
+            camera_path::CameraPath::new(camera_path::Spline::CatmullRom)
+        };
+
+        // Captures draw into a framebuffer of their own, and start playing the path
+        // right away
+        let mut capture = capture_directory.map(|directory| unsafe {
+            capture::FrameCapture::new(&directory, window_size.width, window_size.height)
+        });
+        if capture.is_some() {
+            if camera_path.keys.len() < 2 {
+                println!("Capturing needs a camera path with at least two keys, add them with K");
+                return;
+            }
+            controller = camera::Controller::Path;
+        }
 
-        // This will not change, so no need to recalculate for each frame
-        let fovy = (SCREEN_H as f32) / (SCREEN_W as f32);
-        let offset = 15.0 / HELICOPTER_COUNT as f32;
+        // Flying as a flock instead of each on their own, with weights tuned by number keys
+        let mut flock: Option<steering::Flock> = None;
+        let mut steering_settings = steering::SteeringSettings::default();
+        let mut tuned_weight = 0;
 
         // The main rendering loop
         loop {
             let now = std::time::Instant::now();
-            let elapsed = now.duration_since(first_frame_time).as_secs_f32();
             let delta_time = now.duration_since(last_frame_time).as_secs_f32();
             last_frame_time = now;
 
             // Handle keyboard input
             if let Ok(keys) = pressed_keys.lock() {
-                for key in keys.iter() {
+                if controller == camera::Controller::FreeFly {
+                    let mut direction = glm::Vec3::zeros();
+                    let mut sprint = false;
+                    for key in keys.iter() {
+                        match key {
+                            VirtualKeyCode::A => {
+                                direction.x -= 1.0;
+                            }
+                            VirtualKeyCode::D => {
+                                direction.x += 1.0;
+                            }
+                            VirtualKeyCode::W => {
+                                direction.z += 1.0;
+                            }
+                            VirtualKeyCode::S => {
+                                direction.z -= 1.0;
+                            }
+                            VirtualKeyCode::LShift => {
+                                direction.y -= 1.0;
+                            }
+                            VirtualKeyCode::Space => {
+                                direction.y += 1.0;
+                            }
+                            VirtualKeyCode::LControl => {
+                                sprint = true;
+                            }
+                            _ => {}
+                        }
+                    }
+                    camera.fly(&direction, sprint, delta_time);
+                }
+
+                // Keys that should only do something once per press
+                for key in keys.iter().filter(|key| !previous_keys.contains(key)) {
                     match key {
-                        VirtualKeyCode::A => {
-                            camera.move_left(delta_time);
+                        VirtualKeyCode::O => {
+                            camera.toggle_projection();
+                        }
+                        // Switch between flying around, orbiting and chasing
+                        VirtualKeyCode::C => {
+                            controller = match controller {
+                                camera::Controller::FreeFly => {
+                                    orbit = camera::OrbitController::from_camera(
+                                        &camera,
+                                        ORBIT_DISTANCE,
+                                    );
+                                    camera::Controller::Orbit
+                                }
+                                camera::Controller::Orbit if !helicopters.is_empty() => {
+                                    chase = make_chase(&camera, chase_view);
+                                    selected_helicopter.get_or_insert(0);
+                                    camera::Controller::Chase
+                                }
+                                _ => {
+                                    // Don't drift off with whatever speed was left
+                                    camera.velocity = glm::zero();
+                                    camera::Controller::FreeFly
+                                }
+                            };
+                        }
+                        // Cycle through the helicopters. Orbiting can go back to the
+                        // point it was orbiting before, chasing needs a helicopter
+                        VirtualKeyCode::Tab => {
+                            selected_helicopter = match selected_helicopter {
+                                Some(i) if i + 1 < helicopters.len() => Some(i + 1),
+                                None if !helicopters.is_empty() => Some(0),
+                                _ if controller == camera::Controller::Chase => Some(0),
+                                _ => None,
+                            };
                         }
-                        VirtualKeyCode::D => {
-                            camera.move_right(delta_time);
+                        // Switch between following and sitting in the cockpit
+                        VirtualKeyCode::V => {
+                            chase_view = (chase_view + 1) % CHASE_VIEWS.len();
+                            let (offset, look_at) = CHASE_VIEWS[chase_view];
+                            chase.offset = offset.into();
+                            chase.look_at = look_at.into();
                         }
-                        VirtualKeyCode::W => {
-                            camera.move_forward(delta_time);
+                        // Play the camera path from the start, or stop playing it
+                        VirtualKeyCode::P => {
+                            if controller == camera::Controller::Path {
+                                camera.velocity = glm::zero();
+                                controller = camera::Controller::FreeFly;
+                            } else if camera_path.keys.len() < 2 {
+                                println!(
+                                    "The camera path needs at least two keys, add them with K"
+                                );
+                            } else {
+                                scene_time = 0.0;
+                                for helicopter in helicopters.iter_mut() {
+                                    helicopter.flight.time = 0.0;
+                                }
+                                controller = camera::Controller::Path;
+                            }
+                        }
+                        // Open or close the doors of the selected helicopter, or of all of them
+                        VirtualKeyCode::G => {
+                            for (i, helicopter) in helicopters.iter_mut().enumerate() {
+                                if selected_helicopter.is_none_or(|selected| selected == i) {
+                                    helicopter.door_state.toggle();
+                                }
+                            }
                         }
-                        VirtualKeyCode::S => {
-                            camera.move_backward(delta_time);
+                        // Land the selected helicopter, or all of them, then stop the rotors,
+                        // then take off again. The flock doesn't land, so it has to be left first
+                        VirtualKeyCode::L if flock.is_some() => {
+                            println!("The flock can't land, press F to leave it first");
+                        }
+                        VirtualKeyCode::L => {
+                            for (i, helicopter) in helicopters.iter_mut().enumerate() {
+                                if selected_helicopter.is_none_or(|selected| selected == i) {
+                                    helicopter.throttle = if helicopter.throttle >= 1.0 {
+                                        IDLE_THROTTLE
+                                    } else if helicopter.throttle > 0.0 {
+                                        0.0
+                                    } else {
+                                        1.0
+                                    };
+                                }
+                            }
                         }
-                        VirtualKeyCode::LShift => {
-                            camera.move_down(delta_time);
+                        // Fly as a flock or each on their own
+                        VirtualKeyCode::F => {
+                            flock = match flock {
+                                Some(_) => None,
+                                None => {
+                                    // The flock doesn't land, so everyone takes off
+                                    for helicopter in helicopters.iter_mut() {
+                                        helicopter.throttle = 1.0;
+                                    }
+                                    let positions: Vec<glm::Vec3> =
+                                        helicopters.iter().map(|h| h.body.position).collect();
+                                    Some(steering::Flock::new(&positions))
+                                }
+                            };
                         }
-                        VirtualKeyCode::Space => {
-                            camera.move_up(delta_time);
+                        // Pick a steering weight to tune with + and -
+                        VirtualKeyCode::Key1
+                        | VirtualKeyCode::Key2
+                        | VirtualKeyCode::Key3
+                        | VirtualKeyCode::Key4
+                        | VirtualKeyCode::Key5 => {
+                            tuned_weight = match key {
+                                VirtualKeyCode::Key1 => 0,
+                                VirtualKeyCode::Key2 => 1,
+                                VirtualKeyCode::Key3 => 2,
+                                VirtualKeyCode::Key4 => 3,
+                                _ => 4,
+                            };
+                            println!(
+                                "Tuning steering {} weight, currently {:.3}",
+                                steering::WEIGHT_NAMES[tuned_weight],
+                                steering_settings.weight_mut(tuned_weight)
+                            );
                         }
+                        VirtualKeyCode::Equals | VirtualKeyCode::Minus => {
+                            let weight = steering_settings.weight_mut(tuned_weight);
+                            if *key == VirtualKeyCode::Equals {
+                                *weight *= STEERING_TUNING_STEP;
+                            } else {
+                                *weight /= STEERING_TUNING_STEP;
+                            }
+                            println!(
+                                "Steering {} weight: {:.3}",
+                                steering::WEIGHT_NAMES[tuned_weight],
+                                weight
+                            );
+                        }
+                        // Add a key where the camera is, and save the path right away
+                        VirtualKeyCode::K => {
+                            camera_path.add_camera_key(&camera, CAMERA_PATH_KEY_INTERVAL);
+                            match camera_path.save(CAMERA_PATH_FILE) {
+                                Ok(()) => println!(
+                                    "Added camera path key {} at {:.1}s",
+                                    camera_path.keys.len(),
+                                    camera_path.duration()
+                                ),
+                                Err(e) => println!(
+                                    "Failed to save camera path {}: {}",
+                                    CAMERA_PATH_FILE, e
+                                ),
+                            }
+                        }
+                        // Export the scene as it looks right now
+                        VirtualKeyCode::X => match export_scene(&root_node, &terrain, &meshes) {
+                            Ok(()) => println!(
+                                "Exported scene to {} and {}",
+                                EXPORT_OBJ_FILE, EXPORT_PLY_FILE
+                            ),
+                            Err(e) => println!("Failed to export scene: {}", e),
+                        },
                         _ => {}
                     }
                 }
+                previous_keys = keys.clone();
             }
 
             // Handle mouse movement. delta contains the x and y movement of the mouse since last frame in pixels
             if let Ok(mut delta) = mouse_delta.lock() {
-                camera.move_mouse(delta.0, delta.1);
+                match controller {
+                    camera::Controller::FreeFly => camera.move_mouse(delta.0, delta.1),
+                    camera::Controller::Chase | camera::Controller::Path => {}
+                    camera::Controller::Orbit => {
+                        if let Ok(buttons) = mouse_buttons.lock() {
+                            if buttons.contains(&MouseButton::Left) {
+                                orbit.rotate(delta.0, delta.1);
+                            }
+                            if buttons.contains(&MouseButton::Middle) {
+                                orbit.pan(delta.0, delta.1);
+                            }
+                        }
+                    }
+                }
                 *delta = (0.0, 0.0);
             }
+            if let Ok(mut lines) = scroll_delta.lock() {
+                match controller {
+                    camera::Controller::FreeFly => camera.adjust_speed(*lines),
+                    camera::Controller::Orbit => orbit.zoom(*lines),
+                    camera::Controller::Chase | camera::Controller::Path => {}
+                }
+                *lines = 0.0;
+            }
+
+            // Path playback can step by a fixed amount instead, so captures come out the
+            // same every time whatever the frame rate
+            let delta_time = match (controller, PATH_FIXED_TIMESTEP) {
+                _ if capture.is_some() => 1.0 / CAPTURE_FRAME_RATE,
+                (camera::Controller::Path, Some(step)) => step,
+                _ => delta_time,
+            };
+            let elapsed = scene_time;
 
             for helicopter in helicopters.iter_mut() {
-                helicopter.update_heading(elapsed, offset);
-                helicopter.update_rotors(elapsed, offset);
+                helicopter.update_rotors(delta_time);
+            }
+            match flock.as_mut() {
+                // The whole flock follows the same point along the first flight path,
+                // and steering keeps them apart
+                Some(flock) if !helicopters.is_empty() => {
+                    let leader = helicopters[0].flight_path.position(elapsed as f64);
+                    let (x, z) = (leader.x as f32, leader.y as f32);
+                    let ground = terrain.height_at(x, z).unwrap_or(0.0);
+                    let target = glm::vec3(x, ground + FLOCK_ALTITUDE, z);
+                    let targets = vec![target; helicopters.len()];
+                    flock.update(
+                        &targets,
+                        |x, z| terrain.height_at(x, z),
+                        &steering_settings,
+                        delta_time,
+                    );
+                    for (helicopter, agent) in helicopters.iter_mut().zip(flock.agents.iter()) {
+                        helicopter.follow_agent(agent);
+                    }
+                }
+                _ => {
+                    for helicopter in helicopters.iter_mut() {
+                        helicopter.update_heading(delta_time, |x, z| terrain.height_at(x, z));
+                    }
+                }
+            }
+            for helicopter in helicopters.iter_mut() {
+                helicopter.update_door(delta_time);
             }
 
             unsafe {
+                if let Some(capture) = &capture {
+                    capture.bind();
+                }
                 gl::ClearColor(0.76862745, 0.71372549, 0.94901961, 1.0); // moon raker, full opacity
                 gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
 
                 update_node_transformations(&mut root_node, &glm::Mat4::identity());
-                draw_scene(&root_node, &camera.make_view_transform(fovy));
+
+                // The helicopters have moved, so the camera has to catch up with them
+                match controller {
+                    camera::Controller::Orbit => {
+                        if let Some(i) = selected_helicopter {
+                            orbit.target = helicopters[i].body.world_position();
+                        }
+                        orbit.apply(&mut camera);
+                    }
+                    camera::Controller::Chase => {
+                        let i = selected_helicopter.unwrap_or(0);
+                        let transform = helicopters[i].body.current_transformation_matrix;
+                        chase.update(&transform, delta_time);
+                        chase.apply(&mut camera);
+                    }
+                    camera::Controller::Path => {
+                        camera_path.apply(elapsed, &mut camera);
+                        if camera_path.finished(elapsed) {
+                            camera.velocity = glm::zero();
+                            controller = camera::Controller::FreeFly;
+                        }
+                    }
+                    camera::Controller::FreeFly => {}
+                }
+
+                // Residency goes by where the camera is on the terrain, in its own space
+                let terrain_transform = root_node[0].current_transformation_matrix;
+                let position = glm::inverse(&terrain_transform) * camera.position().push(1.0);
+                let changes = terrain.update_residency(
+                    glm::vec2(position.x, position.z),
+                    TERRAIN_LOAD_DISTANCE,
+                    TERRAIN_EVICT_DISTANCE,
+                );
+                update_terrain_buffers(&mut terrain, &changes);
+
+                let view_transform = camera.make_view_transform();
+                draw_terrain(&terrain, &root_node[0], &view_transform);
+                draw_scene(&root_node, &view_transform);
+
+                // The last frame of the path has been drawn once playback stops
+                if let Some(capture) = capture.as_mut() {
+                    capture.save_frame();
+                    if controller != camera::Controller::Path {
+                        println!("Captured {} frames", capture.frames);
+                        return;
+                    }
+                }
             }
 
-            context.swap_buffers().unwrap();
+            if capture.is_none() {
+                context.swap_buffers().unwrap();
+            }
+            scene_time += delta_time;
         }
     });
 
     // Keep track of the health of the rendering thread
     let render_thread_healthy = Arc::new(RwLock::new(true));
     let render_thread_watchdog = Arc::clone(&render_thread_healthy);
+    let event_loop_proxy = el.create_proxy();
     thread::spawn(move || {
         if !render_thread.join().is_ok() {
             if let Ok(mut health) = render_thread_watchdog.write() {
@@ -391,6 +1203,9 @@ fn main() {
                 *health = false;
             }
         }
+        // Rendering has stopped, either because it panicked or a capture is done, so
+        // wake up the event loop to quit
+        let _ = event_loop_proxy.send_event(());
     });
 
     // Start the event loop -- This is where window events get handled
@@ -411,6 +1226,9 @@ fn main() {
             } => {
                 *control_flow = ControlFlow::Exit;
             }
+            Event::UserEvent(()) => {
+                *control_flow = ControlFlow::Exit;
+            }
             // Keep track of currently pressed keys to send to the rendering thread
             Event::WindowEvent {
                 event:
@@ -452,6 +1270,34 @@ fn main() {
                     _ => {}
                 }
             }
+            // Keep track of held mouse buttons and scrolling as well
+            Event::WindowEvent {
+                event: WindowEvent::MouseInput { state, button, .. },
+                ..
+            } => {
+                if let Ok(mut buttons) = arc_mouse_buttons.lock() {
+                    match state {
+                        Released => buttons.retain(|&held| held != button),
+                        Pressed => {
+                            if !buttons.contains(&button) {
+                                buttons.push(button);
+                            }
+                        }
+                    }
+                }
+            }
+            Event::WindowEvent {
+                event: WindowEvent::MouseWheel { delta, .. },
+                ..
+            } => {
+                if let Ok(mut lines) = arc_scroll_delta.lock() {
+                    *lines += match delta {
+                        MouseScrollDelta::LineDelta(_, y) => y,
+                        // Roughly how many pixels make up a line
+                        MouseScrollDelta::PixelDelta(position) => position.y as f32 / 20.0,
+                    };
+                }
+            }
             Event::DeviceEvent {
                 event: DeviceEvent::MouseMotion { delta },
                 ..
@@ -0,0 +1,37 @@
+use crate::texture::{self, Texture2D};
+
+/// Base color texture unit, matches the binding in simple.frag
+const BASE_COLOR_UNIT: u32 = 0;
+
+/// Surface properties of a drawn object, combined with the vertex colors in the shader
+#[derive(Clone, Copy, Debug)]
+pub struct Material {
+    pub base_color: glm::Vec4,
+    pub texture_id: u32, // 0 if untextured
+}
+
+impl Material {
+    /// A plain white material, leaving the vertex colors as they are
+    pub fn new() -> Self {
+        Material {
+            base_color: glm::vec4(1.0, 1.0, 1.0, 1.0),
+            texture_id: 0,
+        }
+    }
+
+    /// A white material showing the given texture
+    pub fn with_texture(texture: &Texture2D) -> Self {
+        Material {
+            texture_id: texture.id,
+            ..Material::new()
+        }
+    }
+
+    /// Binds the texture and sets the uniforms of this material. Make sure the
+    /// shader is active before calling this
+    pub unsafe fn apply(&self) {
+        gl::Uniform4fv(5, 1, self.base_color.as_ptr());
+        gl::Uniform1i(6, (self.texture_id != 0) as i32);
+        texture::bind_texture(self.texture_id, BASE_COLOR_UNIT);
+    }
+}
@@ -1,38 +1,906 @@
+use std::collections::HashMap;
+
 use tobj;
 
+/// Crease angle used when normals are generated for models that lack them
+pub const DEFAULT_CREASE_ANGLE: f32 = std::f32::consts::PI / 3.0;
+
 // internal helper
 fn generate_color_vec(color: [f32; 4], num: usize) -> Vec<f32> {
     color.iter().cloned().cycle().take(num * 4).collect()
 }
 
+/// How the contribution of each face to a smooth vertex normal is weighted
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug)]
+pub enum NormalWeighting {
+    /// Larger faces pull the normal more towards themselves
+    Area,
+    /// Faces contribute by the angle of the corner they have at the vertex
+    Angle,
+}
+
+/// Strategy used by `Mesh::generate_normals`
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug)]
+pub enum NormalMode {
+    /// Every triangle gets its own vertices and the normal of its face
+    Flat,
+    /// Normals are averaged across faces, but vertices are split wherever two
+    /// faces meet at an angle larger than `crease_angle` (in radians)
+    Smooth {
+        weighting: NormalWeighting,
+        crease_angle: f32,
+    },
+}
+
+/// A problem found by `Mesh::validate`
+#[derive(Debug, PartialEq)]
+pub enum MeshIssue {
+    /// The index at the given position of `indices` points past the last vertex
+    IndexOutOfRange { position: usize, index: u32 },
+    /// `indices` has a length that isn't a multiple of 3
+    IncompleteTriangle { index_length: usize },
+    /// The triangle repeats a vertex or has no area
+    DegenerateTriangle { triangle: usize },
+    /// The position of the vertex contains NaN or infinity
+    NonFinitePosition { vertex: usize },
+    /// A vertex attribute has a different amount of values than there are vertices
+    AttributeLengthMismatch {
+        attribute: &'static str,
+        expected: usize,
+        actual: usize,
+    },
+    /// `index_count` disagrees with the length of `indices`
+    IndexCountMismatch {
+        index_count: i32,
+        index_length: usize,
+    },
+}
+
+impl MeshIssue {
+    /// Whether drawing a mesh with this issue makes OpenGL read outside of its buffers
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            MeshIssue::IndexOutOfRange { .. } | MeshIssue::AttributeLengthMismatch { .. } => true,
+            MeshIssue::IndexCountMismatch {
+                index_count,
+                index_length,
+            } => *index_count < 0 || *index_count as usize > *index_length,
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for MeshIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MeshIssue::IndexOutOfRange { position, index } => {
+                write!(
+                    f,
+                    "index {} at position {} is out of range",
+                    index, position
+                )
+            }
+            MeshIssue::IncompleteTriangle { index_length } => write!(
+                f,
+                "{} indices is not a whole number of triangles",
+                index_length
+            ),
+            MeshIssue::DegenerateTriangle { triangle } => {
+                write!(f, "triangle {} is degenerate", triangle)
+            }
+            MeshIssue::NonFinitePosition { vertex } => {
+                write!(f, "vertex {} has a non-finite position", vertex)
+            }
+            MeshIssue::AttributeLengthMismatch {
+                attribute,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} has {} values, expected {}",
+                attribute, actual, expected
+            ),
+            MeshIssue::IndexCountMismatch {
+                index_count,
+                index_length,
+            } => write!(
+                f,
+                "index_count is {}, but there are {} indices",
+                index_count, index_length
+            ),
+        }
+    }
+}
+
+/// What `Mesh::repair` had to change
+#[derive(Debug, Default)]
+pub struct RepairReport {
+    pub welded_vertices: usize,
+    pub removed_triangles: usize,
+    pub flipped_triangles: usize,
+}
+
+#[derive(Clone)]
 pub struct Mesh {
     pub vertices: Vec<f32>,
     pub normals: Vec<f32>,
     pub colors: Vec<f32>,
+    pub texcoords: Vec<f32>,
+    /// Tangent in xyz, with the handedness of the bitangent in w
+    pub tangents: Vec<f32>,
     pub indices: Vec<u32>,
     pub index_count: i32,
 }
 
 impl Mesh {
+    /// Converts a loaded OBJ mesh, repairing it first if `validate` finds problems
     pub fn from(mesh: tobj::Mesh, color: [f32; 4]) -> Self {
         let num_verts = mesh.positions.len() / 3;
         let index_count = mesh.indices.len() as i32;
-        Mesh {
+        let mut result = Mesh {
             vertices: mesh.positions,
             normals: mesh.normals,
             indices: mesh.indices,
             colors: generate_color_vec(color, num_verts),
+            texcoords: mesh.texcoords,
+            tangents: Vec::new(),
+            index_count,
+        };
+        if let Err(issues) = result.validate() {
+            let report = result.repair();
+            println!(
+                "Repaired {} mesh issues: welded {} vertices, removed {} triangles and \
+                 flipped {} triangles.",
+                issues.len(),
+                report.welded_vertices,
+                report.removed_triangles,
+                report.flipped_triangles
+            );
+        }
+        result.generate_missing_attributes();
+        result
+    }
+
+    /// Makes a white mesh from raw positions and triangle indices, without any
+    /// other vertex attributes
+    pub fn new(vertices: Vec<f32>, indices: Vec<u32>) -> Self {
+        let num_verts = vertices.len() / 3;
+        let index_count = indices.len() as i32;
+        Mesh {
+            vertices,
+            normals: Vec::new(),
+            colors: generate_color_vec([1.0, 1.0, 1.0, 1.0], num_verts),
+            texcoords: Vec::new(),
+            tangents: Vec::new(),
+            indices,
             index_count,
         }
     }
+
+    /// Generates normals if the mesh has none, and tangents if it has texture
+    /// coordinates to derive them from
+    pub fn generate_missing_attributes(&mut self) {
+        // Leave broken meshes alone, so validate can explain what is wrong with them
+        let vertex_count = self.vertex_count() as u32;
+        if self.indices.iter().any(|&index| index >= vertex_count) {
+            return;
+        }
+
+        // The shader always reads normals, so make some up if the model has none
+        if self.normals.len() != self.vertices.len() {
+            self.generate_normals(NormalMode::Smooth {
+                weighting: NormalWeighting::Angle,
+                crease_angle: DEFAULT_CREASE_ANGLE,
+            });
+        }
+
+        if self.texcoords.len() == self.vertex_count() * 2 {
+            self.generate_tangents();
+        }
+    }
+
+    /// Number of vertices in this mesh
+    pub fn vertex_count(&self) -> usize {
+        self.vertices.len() / 3
+    }
+
+    /// Position of the vertex with the given index
+    pub fn position(&self, index: usize) -> glm::Vec3 {
+        glm::vec3(
+            self.vertices[index * 3],
+            self.vertices[index * 3 + 1],
+            self.vertices[index * 3 + 2],
+        )
+    }
+
+    /// Sphere containing all vertices, as the center in xyz and the radius in w. Not the
+    /// smallest possible, but close enough for culling and picking levels of detail
+    pub fn bounding_sphere(&self) -> glm::Vec4 {
+        if self.vertex_count() == 0 {
+            return glm::zero();
+        }
+
+        let (mut min, mut max) = (self.position(0), self.position(0));
+        for vertex in 1..self.vertex_count() {
+            let p = self.position(vertex);
+            min = glm::min2(&min, &p);
+            max = glm::max2(&max, &p);
+        }
+        let center = (min + max) / 2.0;
+        let radius = (0..self.vertex_count())
+            .map(|vertex| glm::distance(&center, &self.position(vertex)))
+            .fold(0.0, f32::max);
+        glm::vec4(center.x, center.y, center.z, radius)
+    }
+
+    /// Appends a copy of all attributes of the given vertex, returning the index of the copy
+    fn duplicate_vertex(&mut self, index: usize) -> u32 {
+        let new_index = self.vertex_count() as u32;
+        self.vertices.extend_from_within(index * 3..index * 3 + 3);
+        if self.colors.len() >= (index + 1) * 4 {
+            self.colors.extend_from_within(index * 4..index * 4 + 4);
+        }
+        if self.normals.len() >= (index + 1) * 3 {
+            self.normals.extend_from_within(index * 3..index * 3 + 3);
+        }
+        if self.texcoords.len() >= (index + 1) * 2 {
+            self.texcoords.extend_from_within(index * 2..index * 2 + 2);
+        }
+        if self.tangents.len() >= (index + 1) * 4 {
+            self.tangents.extend_from_within(index * 4..index * 4 + 4);
+        }
+        new_index
+    }
+
+    /// Makes a copy of this mesh with the given transformation baked into its vertices
+    pub fn transformed(&self, transform: &glm::Mat4) -> Mesh {
+        let mut result = self.clone();
+        let linear = glm::mat4_to_mat3(transform);
+        // Normals have to be transformed by the inverse transpose to stay perpendicular
+        // to the surface when the transformation scales non-uniformly
+        let normal_matrix = glm::inverse_transpose(linear);
+
+        for position in result.vertices.chunks_exact_mut(3) {
+            let p = transform * glm::vec4(position[0], position[1], position[2], 1.0);
+            position.copy_from_slice(&[p.x, p.y, p.z]);
+        }
+        for normal in result.normals.chunks_exact_mut(3) {
+            let n = glm::normalize(&(normal_matrix * glm::vec3(normal[0], normal[1], normal[2])));
+            normal.copy_from_slice(n.as_slice());
+        }
+        for tangent in result.tangents.chunks_exact_mut(4) {
+            let t = glm::normalize(&(linear * glm::vec3(tangent[0], tangent[1], tangent[2])));
+            tangent[..3].copy_from_slice(t.as_slice());
+        }
+
+        // Mirroring transformations turn the triangles inside out
+        if glm::determinant(&linear) < 0.0 {
+            for triangle in result.indices.chunks_exact_mut(3) {
+                triangle.swap(1, 2);
+            }
+        }
+
+        result
+    }
+
+    /// Makes a new mesh out of the given indices into this one, only copying the
+    /// vertices they use
+    pub fn submesh(&self, indices: &[u32]) -> Mesh {
+        let mut remap: Vec<Option<u32>> = vec![None; self.vertex_count()];
+        let mut used: Vec<usize> = Vec::new();
+        let new_indices: Vec<u32> = indices
+            .iter()
+            .map(|&index| {
+                *remap[index as usize].get_or_insert_with(|| {
+                    used.push(index as usize);
+                    (used.len() - 1) as u32
+                })
+            })
+            .collect();
+
+        let copy = |values: &Vec<f32>, components: usize| -> Vec<f32> {
+            if values.len() != self.vertex_count() * components {
+                return Vec::new();
+            }
+            used.iter()
+                .flat_map(|&i| values[i * components..(i + 1) * components].iter())
+                .cloned()
+                .collect()
+        };
+        let mut result = Mesh::new(copy(&self.vertices, 3), new_indices);
+        result.colors = copy(&self.colors, 4);
+        result.normals = copy(&self.normals, 3);
+        result.texcoords = copy(&self.texcoords, 2);
+        result.tangents = copy(&self.tangents, 4);
+        result
+    }
+
+    /// Appends all vertices and triangles of another mesh to this one. Vertex
+    /// attributes that only one of the meshes has are dropped
+    pub fn append(&mut self, other: &Mesh) {
+        let offset = self.vertex_count() as u32;
+        let merge = |own: &mut Vec<f32>, other_values: &Vec<f32>, own_count, other_count| {
+            if own.len() == own_count && other_values.len() == other_count {
+                own.extend(other_values.iter());
+            } else {
+                own.clear();
+            }
+        };
+        let (own_verts, other_verts) = (self.vertex_count(), other.vertex_count());
+        merge(
+            &mut self.colors,
+            &other.colors,
+            own_verts * 4,
+            other_verts * 4,
+        );
+        merge(
+            &mut self.normals,
+            &other.normals,
+            own_verts * 3,
+            other_verts * 3,
+        );
+        merge(
+            &mut self.texcoords,
+            &other.texcoords,
+            own_verts * 2,
+            other_verts * 2,
+        );
+        merge(
+            &mut self.tangents,
+            &other.tangents,
+            own_verts * 4,
+            other_verts * 4,
+        );
+
+        self.vertices.extend(other.vertices.iter());
+        self.indices
+            .extend(other.indices.iter().map(|index| index + offset));
+        self.index_count = self.indices.len() as i32;
+    }
+
+    /// (Re)generates the normals of this mesh, replacing any existing ones. This may
+    /// add vertices, as vertices on hard edges need one normal per side
+    pub fn generate_normals(&mut self, mode: NormalMode) {
+        match mode {
+            NormalMode::Flat => self.generate_flat_normals(),
+            NormalMode::Smooth {
+                weighting,
+                crease_angle,
+            } => self.generate_smooth_normals(weighting, crease_angle),
+        }
+    }
+
+    /// Normal of the vertex with the given index
+    pub fn normal(&self, index: usize) -> glm::Vec3 {
+        glm::vec3(
+            self.normals[index * 3],
+            self.normals[index * 3 + 1],
+            self.normals[index * 3 + 2],
+        )
+    }
+
+    /// Texture coordinate of the vertex with the given index
+    pub fn texcoord(&self, index: usize) -> glm::Vec2 {
+        glm::vec2(self.texcoords[index * 2], self.texcoords[index * 2 + 1])
+    }
+
+    /// (Re)generates per vertex tangents from positions, normals and texture coordinates,
+    /// following the approach of MikkTSpace. Vertices shared by triangles with mirrored
+    /// texture coordinates are split, so every vertex has a single handedness. Does nothing
+    /// if the mesh lacks normals or texture coordinates
+    pub fn generate_tangents(&mut self) {
+        self.tangents.clear();
+        if self.normals.len() != self.vertices.len()
+            || self.texcoords.len() != self.vertex_count() * 2
+        {
+            return;
+        }
+
+        let triangle_count = self.indices.len() / 3;
+
+        // Tangent and bitangent of every triangle, derived from how the texture
+        // coordinates change along its edges
+        let face_frames: Vec<(glm::Vec3, glm::Vec3)> = (0..triangle_count)
+            .map(|triangle| {
+                let corner = |k: usize| self.indices[triangle * 3 + k] as usize;
+                let (a, b, c) = (corner(0), corner(1), corner(2));
+                let edge1 = self.position(b) - self.position(a);
+                let edge2 = self.position(c) - self.position(a);
+                let uv1 = self.texcoord(b) - self.texcoord(a);
+                let uv2 = self.texcoord(c) - self.texcoord(a);
+
+                let det = uv1.x * uv2.y - uv2.x * uv1.y;
+                if det.abs() < f32::EPSILON {
+                    return (glm::zero(), glm::zero());
+                }
+
+                let tangent = (edge1 * uv2.y - edge2 * uv1.y) / det;
+                let bitangent = (edge2 * uv1.x - edge1 * uv2.x) / det;
+                (tangent, bitangent)
+            })
+            .collect();
+
+        let handedness = |normal: &glm::Vec3, (tangent, bitangent): &(glm::Vec3, glm::Vec3)| {
+            if glm::dot(&glm::cross(normal, tangent), bitangent) < 0.0 {
+                -1.0
+            } else {
+                1.0
+            }
+        };
+
+        // Split vertices where triangles disagree on handedness
+        let mut vertex_corners: Vec<Vec<usize>> = vec![Vec::new(); self.vertex_count()];
+        for (corner, &index) in self.indices.iter().enumerate() {
+            vertex_corners[index as usize].push(corner);
+        }
+        let mut corner_signs = vec![1.0f32; self.indices.len()];
+        for (vertex, corners) in vertex_corners.iter().enumerate() {
+            let normal = self.normal(vertex);
+            let mut mirrored: Option<u32> = None;
+            let mut first_sign = None;
+            for &corner in corners {
+                let sign = handedness(&normal, &face_frames[corner / 3]);
+                corner_signs[corner] = sign;
+                match first_sign {
+                    None => first_sign = Some(sign),
+                    Some(first) if first != sign => {
+                        let copy = match mirrored {
+                            Some(copy) => copy,
+                            None => self.duplicate_vertex(vertex),
+                        };
+                        mirrored = Some(copy);
+                        self.indices[corner] = copy;
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // Accumulate the tangents of all triangles, weighted by the angle of their corners
+        // and projected onto the tangent plane of each vertex
+        let mut accumulated = vec![glm::Vec3::zeros(); self.vertex_count()];
+        let mut signs = vec![1.0f32; self.vertex_count()];
+        for (corner, &index) in self.indices.iter().enumerate() {
+            let index = index as usize;
+            let base = corner / 3 * 3;
+            let at = |k: usize| self.position(self.indices[base + (corner + k) % 3] as usize);
+            let (p, next, prev) = (at(0), at(1), at(2));
+            let angle = glm::angle(&(next - p), &(prev - p));
+            if !angle.is_finite() {
+                continue;
+            }
+
+            let normal = self.normal(index);
+            let tangent = face_frames[corner / 3].0;
+            let projected = tangent - normal * glm::dot(&normal, &tangent);
+            let length = glm::length(&projected);
+            if length > 0.0 {
+                accumulated[index] += projected / length * angle;
+            }
+            signs[index] = corner_signs[corner];
+        }
+
+        self.tangents = Vec::with_capacity(self.vertex_count() * 4);
+        for (index, tangent) in accumulated.iter().enumerate() {
+            let normal = self.normal(index);
+            // Orthogonalize once more, as the sum may have drifted off the tangent plane
+            let mut tangent = tangent - normal * glm::dot(&normal, tangent);
+            if glm::length(&tangent) < f32::EPSILON {
+                // No usable texture coordinates, any direction in the tangent plane will do
+                let helper = if normal.x.abs() < 0.9 {
+                    glm::vec3(1.0, 0.0, 0.0)
+                } else {
+                    glm::vec3(0.0, 1.0, 0.0)
+                };
+                tangent = glm::cross(&normal, &helper);
+            }
+            let tangent = glm::normalize(&tangent);
+            self.tangents
+                .extend([tangent.x, tangent.y, tangent.z, signs[index]].iter());
+        }
+    }
+
+    /// Unnormalized normal of the given triangle. Its length is twice the triangle's area
+    fn face_normal(&self, triangle: usize) -> glm::Vec3 {
+        let a = self.position(self.indices[triangle * 3] as usize);
+        let b = self.position(self.indices[triangle * 3 + 1] as usize);
+        let c = self.position(self.indices[triangle * 3 + 2] as usize);
+        glm::cross(&(b - a), &(c - a))
+    }
+
+    fn generate_flat_normals(&mut self) {
+        let triangle_count = self.indices.len() / 3;
+        // Normals are thrown away anyway, so don't bother copying them around
+        self.normals.clear();
+
+        // Give every corner of every triangle its own vertex. The first time a vertex is
+        // used we can keep it as is
+        let mut used = vec![false; self.vertex_count()];
+        for i in 0..self.indices.len() {
+            let index = self.indices[i] as usize;
+            if used[index] {
+                self.indices[i] = self.duplicate_vertex(index);
+            } else {
+                used[index] = true;
+            }
+        }
+
+        let mut normals = vec![0.0; self.vertices.len()];
+        for triangle in 0..triangle_count {
+            // Triangles without area have no direction, so leave their normals at zero
+            let normal = self.face_normal(triangle);
+            let length = glm::length(&normal);
+            let normal = if length > 0.0 {
+                normal / length
+            } else {
+                normal
+            };
+            for &index in &self.indices[triangle * 3..triangle * 3 + 3] {
+                let index = index as usize;
+                normals[index * 3..index * 3 + 3].copy_from_slice(normal.as_slice());
+            }
+        }
+        self.normals = normals;
+    }
+
+    fn generate_smooth_normals(&mut self, weighting: NormalWeighting, crease_angle: f32) {
+        let triangle_count = self.indices.len() / 3;
+        let crease_cos = crease_angle.cos();
+        self.normals.clear();
+
+        let face_normals: Vec<glm::Vec3> = (0..triangle_count)
+            .map(|triangle| self.face_normal(triangle))
+            .collect();
+        let unit_normals: Vec<glm::Vec3> = face_normals
+            .iter()
+            .map(|n| {
+                let length = glm::length(n);
+                if length > 0.0 {
+                    n / length
+                } else {
+                    glm::zero()
+                }
+            })
+            .collect();
+
+        // Weighted contribution of the triangle a corner belongs to
+        let corner_weight = |mesh: &Mesh, corner: usize| -> glm::Vec3 {
+            let triangle = corner / 3;
+            match weighting {
+                NormalWeighting::Area => face_normals[triangle],
+                NormalWeighting::Angle => {
+                    let base = triangle * 3;
+                    let at =
+                        |k: usize| mesh.position(mesh.indices[base + (corner + k) % 3] as usize);
+                    let (p, next, prev) = (at(0), at(1), at(2));
+                    let angle = glm::angle(&(next - p), &(prev - p));
+                    if angle.is_finite() {
+                        unit_normals[triangle] * angle
+                    } else {
+                        glm::zero()
+                    }
+                }
+            }
+        };
+
+        // Find all corners that reference each vertex
+        let mut vertex_corners: Vec<Vec<usize>> = vec![Vec::new(); self.vertex_count()];
+        for (corner, &index) in self.indices.iter().enumerate() {
+            vertex_corners[index as usize].push(corner);
+        }
+
+        let mut normals: Vec<f32> = vec![0.0; self.vertices.len()];
+        for (vertex, corners) in vertex_corners.iter().enumerate() {
+            // Normal for each corner, only including faces within the crease angle
+            let corner_normals: Vec<glm::Vec3> = corners
+                .iter()
+                .map(|&corner| {
+                    let own = unit_normals[corner / 3];
+                    let sum = corners
+                        .iter()
+                        .filter(|&&other| glm::dot(&own, &unit_normals[other / 3]) >= crease_cos)
+                        .fold(glm::Vec3::zeros(), |acc, &other| {
+                            acc + corner_weight(self, other)
+                        });
+                    if glm::length(&sum) > 0.0 {
+                        glm::normalize(&sum)
+                    } else {
+                        own
+                    }
+                })
+                .collect();
+
+            // Corners that ended up with the same normal can share a vertex, the rest
+            // get a copy. The first group keeps the original vertex
+            let mut groups: Vec<(glm::Vec3, u32)> = Vec::new();
+            for (&corner, normal) in corners.iter().zip(&corner_normals) {
+                let (normal, target) =
+                    match groups.iter().find(|(n, _)| glm::dot(n, normal) > 0.9999) {
+                        Some(&group) => group,
+                        None => {
+                            let target = if groups.is_empty() {
+                                vertex as u32
+                            } else {
+                                self.duplicate_vertex(vertex)
+                            };
+                            groups.push((*normal, target));
+                            (*normal, target)
+                        }
+                    };
+                self.indices[corner] = target;
+
+                let target = target as usize;
+                normals.resize(self.vertices.len(), 0.0);
+                normals[target * 3..target * 3 + 3].copy_from_slice(normal.as_slice());
+            }
+        }
+
+        self.normals = normals;
+    }
+
+    /// Checks the mesh for anything that would make it render incorrectly, or make
+    /// OpenGL read outside of its buffers
+    pub fn validate(&self) -> Result<(), Vec<MeshIssue>> {
+        let mut issues = Vec::new();
+        let vertex_count = self.vertex_count();
+
+        if !self.vertices.len().is_multiple_of(3) {
+            issues.push(MeshIssue::AttributeLengthMismatch {
+                attribute: "vertices",
+                expected: vertex_count * 3,
+                actual: self.vertices.len(),
+            });
+        }
+
+        // Colors are always uploaded, the rest may be left out entirely
+        let attributes = [
+            ("colors", &self.colors, 4, false),
+            ("normals", &self.normals, 3, true),
+            ("texcoords", &self.texcoords, 2, true),
+            ("tangents", &self.tangents, 4, true),
+        ];
+        for &(attribute, values, components, optional) in attributes.iter() {
+            let expected = vertex_count * components;
+            if values.len() != expected && !(optional && values.is_empty()) {
+                issues.push(MeshIssue::AttributeLengthMismatch {
+                    attribute,
+                    expected,
+                    actual: values.len(),
+                });
+            }
+        }
+
+        if self.index_count < 0 || self.index_count as usize != self.indices.len() {
+            issues.push(MeshIssue::IndexCountMismatch {
+                index_count: self.index_count,
+                index_length: self.indices.len(),
+            });
+        }
+        if !self.indices.len().is_multiple_of(3) {
+            issues.push(MeshIssue::IncompleteTriangle {
+                index_length: self.indices.len(),
+            });
+        }
+
+        for (position, &index) in self.indices.iter().enumerate() {
+            if index as usize >= vertex_count {
+                issues.push(MeshIssue::IndexOutOfRange { position, index });
+            }
+        }
+
+        for vertex in 0..vertex_count {
+            if !self.position(vertex).iter().all(|v| v.is_finite()) {
+                issues.push(MeshIssue::NonFinitePosition { vertex });
+            }
+        }
+
+        for (triangle, corners) in self.indices.chunks_exact(3).enumerate() {
+            if corners.iter().any(|&index| index as usize >= vertex_count) {
+                continue;
+            }
+            if self.is_degenerate(corners) {
+                issues.push(MeshIssue::DegenerateTriangle { triangle });
+            }
+        }
+
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(issues)
+        }
+    }
+
+    fn is_degenerate(&self, corners: &[u32]) -> bool {
+        let (a, b, c) = (corners[0], corners[1], corners[2]);
+        if a == b || b == c || a == c {
+            return true;
+        }
+        let (pa, pb, pc) = (
+            self.position(a as usize),
+            self.position(b as usize),
+            self.position(c as usize),
+        );
+        // Compare the area to the size of the triangle, so it works the same at any scale
+        let longest = glm::distance2(&pa, &pb)
+            .max(glm::distance2(&pb, &pc))
+            .max(glm::distance2(&pc, &pa));
+        glm::length(&glm::cross(&(pb - pa), &(pc - pa))) <= f32::EPSILON * longest
+    }
+
+    /// Fixes what `validate` complains about: Broken attributes are dropped (and
+    /// regenerated where possible), triangles with bad indices, non-finite positions or
+    /// no area are removed, identical vertices are welded and triangles whose winding
+    /// disagrees with the vertex normals are flipped
+    pub fn repair(&mut self) -> RepairReport {
+        let mut report = RepairReport::default();
+
+        self.vertices.truncate(self.vertex_count() * 3);
+        let vertex_count = self.vertex_count();
+        if self.colors.len() != vertex_count * 4 {
+            self.colors = generate_color_vec([1.0, 1.0, 1.0, 1.0], vertex_count);
+        }
+        if self.normals.len() != vertex_count * 3 {
+            self.normals.clear();
+        }
+        if self.texcoords.len() != vertex_count * 2 {
+            self.texcoords.clear();
+        }
+        if self.tangents.len() != vertex_count * 4 {
+            self.tangents.clear();
+        }
+
+        // Drop incomplete triangles and triangles we can't draw
+        let triangle_count = self.indices.len() / 3;
+        self.indices.truncate(triangle_count * 3);
+        let usable = |mesh: &Mesh, corners: &[u32]| {
+            corners.iter().all(|&index| {
+                (index as usize) < vertex_count
+                    && mesh.position(index as usize).iter().all(|v| v.is_finite())
+            })
+        };
+        let indices: Vec<u32> = self
+            .indices
+            .chunks_exact(3)
+            .filter(|corners| usable(self, corners))
+            .flatten()
+            .cloned()
+            .collect();
+        self.indices = indices;
+
+        report.welded_vertices = self.weld_identical_vertices();
+
+        let indices: Vec<u32> = self
+            .indices
+            .chunks_exact(3)
+            .filter(|corners| !self.is_degenerate(corners))
+            .flatten()
+            .cloned()
+            .collect();
+        self.indices = indices;
+        report.removed_triangles = triangle_count - self.indices.len() / 3;
+
+        // Trust the normals over the winding, as they are usually what the artist saw
+        if self.normals.len() == self.vertices.len() {
+            for triangle in 0..self.indices.len() / 3 {
+                let corners = &self.indices[triangle * 3..triangle * 3 + 3];
+                let average = corners.iter().fold(glm::Vec3::zeros(), |acc, &index| {
+                    acc + self.normal(index as usize)
+                });
+                if glm::dot(&self.face_normal(triangle), &average) < 0.0 {
+                    self.indices.swap(triangle * 3 + 1, triangle * 3 + 2);
+                    report.flipped_triangles += 1;
+                }
+            }
+        }
+
+        self.index_count = self.indices.len() as i32;
+        self.generate_missing_attributes();
+        report
+    }
+
+    /// Merges vertices whose attributes are all identical, and removes vertices no
+    /// triangle uses. Returns how many vertices were removed
+    fn weld_identical_vertices(&mut self) -> usize {
+        let old_count = self.vertex_count();
+        let streams: [(&Vec<f32>, usize); 5] = [
+            (&self.vertices, 3),
+            (&self.colors, 4),
+            (&self.normals, 3),
+            (&self.texcoords, 2),
+            (&self.tangents, 4),
+        ];
+
+        let mut lookup: HashMap<Vec<u32>, u32> = HashMap::new();
+        let mut remap: Vec<Option<u32>> = vec![None; old_count];
+        let mut kept: Vec<usize> = Vec::new();
+        for &index in &self.indices {
+            let index = index as usize;
+            if remap[index].is_some() {
+                continue;
+            }
+            let key: Vec<u32> = streams
+                .iter()
+                .filter(|(values, _)| !values.is_empty())
+                .flat_map(|&(values, components)| {
+                    values[index * components..(index + 1) * components]
+                        .iter()
+                        // Treat -0.0 and 0.0 as the same value
+                        .map(|v| (v + 0.0).to_bits())
+                })
+                .collect();
+            let new_index = *lookup.entry(key).or_insert_with(|| {
+                kept.push(index);
+                (kept.len() - 1) as u32
+            });
+            remap[index] = Some(new_index);
+        }
+
+        let compact = |values: &Vec<f32>, components: usize| -> Vec<f32> {
+            kept.iter()
+                .flat_map(|&index| values[index * components..(index + 1) * components].iter())
+                .cloned()
+                .collect()
+        };
+        self.vertices = compact(&self.vertices, 3);
+        self.colors = compact(&self.colors, 4);
+        if !self.normals.is_empty() {
+            self.normals = compact(&self.normals, 3);
+        }
+        if !self.texcoords.is_empty() {
+            self.texcoords = compact(&self.texcoords, 2);
+        }
+        if !self.tangents.is_empty() {
+            self.tangents = compact(&self.tangents, 4);
+        }
+        for index in self.indices.iter_mut() {
+            *index = remap[*index as usize].unwrap();
+        }
+
+        old_count - kept.len()
+    }
+}
+
+/// Materials of an OBJ model, or none if its MTL file couldn't be loaded
+fn obj_materials(
+    path: &str,
+    materials: Result<Vec<tobj::Material>, tobj::LoadError>,
+) -> Vec<tobj::Material> {
+    materials.unwrap_or_else(|e| {
+        println!("Failed to load the materials of {}: {}", path, e);
+        Vec::new()
+    })
+}
+
+/// Path of the diffuse texture of the model's material, if it has one that exists.
+/// MTL files give texture paths relative to the model
+fn diffuse_texture(
+    path: &str,
+    model: &tobj::Model,
+    materials: &[tobj::Material],
+) -> Option<String> {
+    let material = materials.get(model.mesh.material_id?)?;
+    if material.diffuse_texture.is_empty() {
+        return None;
+    }
+    let texture = std::path::Path::new(path)
+        .parent()?
+        .join(&material.diffuse_texture);
+    if !texture.exists() {
+        println!("Texture {} of {} is missing", texture.display(), path);
+        return None;
+    }
+    Some(texture.to_string_lossy().into_owned())
 }
 
 pub struct Terrain;
 impl Terrain {
-    pub fn load(path: &str) -> Mesh {
+    /// Loads the terrain, along with the path of its diffuse texture if it has one
+    pub fn load(path: &str) -> (Mesh, Option<String>) {
         println!("Loading terrain model...");
         let before = std::time::Instant::now();
-        let (models, _materials) = tobj::load_obj(
+        let (models, materials) = tobj::load_obj(
             path,
             &tobj::LoadOptions {
                 triangulate: true,
@@ -62,7 +930,8 @@ impl Terrain {
             terrain.mesh.indices.len() / 3,
         );
 
-        Mesh::from(terrain.mesh, [1.0, 1.0, 1.0, 1.0])
+        let texture = diffuse_texture(path, &terrain, &obj_materials(path, materials));
+        (Mesh::from(terrain.mesh, [1.0, 1.0, 1.0, 1.0]), texture)
     }
 }
 
@@ -72,6 +941,8 @@ pub struct Helicopter {
     pub door: Mesh,
     pub main_rotor: Mesh,
     pub tail_rotor: Mesh,
+    /// Diffuse texture of each part, in the same order as indexing the parts
+    pub textures: [Option<String>; 4],
 }
 
 // You can use square brackets to access the components of the helicopter, if you want to use loops!
@@ -92,7 +963,7 @@ impl Helicopter {
     pub fn load(path: &str) -> Self {
         println!("Loading helicopter model...");
         let before = std::time::Instant::now();
-        let (models, _materials) = tobj::load_obj(
+        let (models, materials) = tobj::load_obj(
             path,
             &tobj::LoadOptions {
                 triangulate: true,
@@ -101,6 +972,7 @@ impl Helicopter {
             },
         )
         .expect("Failed to load helicopter model");
+        let materials = obj_materials(path, materials);
         let after = std::time::Instant::now();
         println!(
             "Done in {:.3}ms!",
@@ -137,11 +1009,214 @@ impl Helicopter {
             .expect("Incorrect model file!")
             .to_owned();
 
+        let textures = [
+            diffuse_texture(path, &body_model, &materials),
+            diffuse_texture(path, &main_rotor_model, &materials),
+            diffuse_texture(path, &tail_rotor_model, &materials),
+            diffuse_texture(path, &door_model, &materials),
+        ];
+        // Textured parts show the colors of their texture instead
+        let color = |part: usize, color: [f32; 4]| match textures[part] {
+            Some(_) => [1.0, 1.0, 1.0, 1.0],
+            None => color,
+        };
+
         Helicopter {
-            body: Mesh::from(body_model.mesh, [0.3, 0.3, 0.3, 1.0]),
-            door: Mesh::from(door_model.mesh, [0.1, 0.1, 0.3, 1.0]),
-            main_rotor: Mesh::from(main_rotor_model.mesh, [0.3, 0.1, 0.1, 1.0]),
-            tail_rotor: Mesh::from(tail_rotor_model.mesh, [0.1, 0.3, 0.1, 1.0]),
+            body: Mesh::from(body_model.mesh, color(0, [0.3, 0.3, 0.3, 1.0])),
+            door: Mesh::from(door_model.mesh, color(3, [0.1, 0.1, 0.3, 1.0])),
+            main_rotor: Mesh::from(main_rotor_model.mesh, color(1, [0.3, 0.1, 0.1, 1.0])),
+            tail_rotor: Mesh::from(tail_rotor_model.mesh, color(2, [0.1, 0.3, 0.1, 1.0])),
+            textures,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A cube from -1 to 1 with its corners shared by all faces, wound counter-clockwise
+    /// seen from the outside
+    fn cube() -> Mesh {
+        let mut vertices = Vec::new();
+        for i in 0..8 {
+            let bit = |b: usize| if i & (1 << b) != 0 { 1.0 } else { -1.0 };
+            vertices.extend([bit(0), bit(1), bit(2)].iter());
         }
+        #[rustfmt::skip]
+        let indices = vec![
+            0, 2, 3, 0, 3, 1, // -z
+            4, 5, 7, 4, 7, 6, // +z
+            0, 4, 6, 0, 6, 2, // -x
+            1, 3, 7, 1, 7, 5, // +x
+            0, 1, 5, 0, 5, 4, // -y
+            2, 6, 7, 2, 7, 3, // +y
+        ];
+        Mesh::new(vertices, indices)
+    }
+
+    fn smooth(weighting: NormalWeighting, degrees: f32) -> NormalMode {
+        NormalMode::Smooth {
+            weighting,
+            crease_angle: degrees.to_radians(),
+        }
+    }
+
+    fn assert_close(a: glm::Vec3, b: glm::Vec3) {
+        assert!(glm::distance(&a, &b) < 1e-5, "{:?} is not {:?}", a, b);
+    }
+
+    #[test]
+    fn cube_normals() {
+        // Every corner is split into one vertex per face
+        let mut mesh = cube();
+        mesh.generate_normals(smooth(NormalWeighting::Angle, 30.0));
+        assert_eq!(mesh.vertex_count(), 24);
+        for triangle in 0..12 {
+            let face = glm::normalize(&mesh.face_normal(triangle));
+            for &index in &mesh.indices[triangle * 3..triangle * 3 + 3] {
+                assert_close(mesh.normal(index as usize), face);
+            }
+        }
+
+        // Faces are split into two triangles at some corners and one at others, but
+        // angle weighting still points every corner straight out from the center
+        let mut mesh = cube();
+        mesh.generate_normals(smooth(NormalWeighting::Angle, 100.0));
+        assert_eq!(mesh.vertex_count(), 8);
+        for vertex in 0..8 {
+            assert_close(mesh.normal(vertex), glm::normalize(&mesh.position(vertex)));
+        }
+
+        // Flat normals give every corner its own vertex
+        let mut mesh = cube();
+        mesh.generate_normals(NormalMode::Flat);
+        assert_eq!(mesh.vertex_count(), 36);
+        assert!(mesh.validate().is_ok());
+    }
+
+    #[test]
+    fn normal_weighting() {
+        // Two right angled triangles meeting at the origin, a small one facing +z and one
+        // four times as large facing +x
+        let vertices = vec![
+            0.0, 0.0, 0.0, //
+            1.0, 0.0, 0.0, //
+            0.0, 1.0, 0.0, //
+            0.0, 2.0, 0.0, //
+            0.0, 0.0, 2.0, //
+        ];
+        let indices = vec![0, 1, 2, 0, 3, 4];
+
+        let mut mesh = Mesh::new(vertices.clone(), indices.clone());
+        mesh.generate_normals(smooth(NormalWeighting::Angle, 100.0));
+        assert_close(mesh.normal(0), glm::normalize(&glm::vec3(1.0, 0.0, 1.0)));
+
+        let mut mesh = Mesh::new(vertices, indices);
+        mesh.generate_normals(smooth(NormalWeighting::Area, 100.0));
+        assert_close(mesh.normal(0), glm::normalize(&glm::vec3(4.0, 0.0, 1.0)));
+    }
+
+    #[test]
+    fn mirrored_tangents() {
+        // Two quads side by side facing +z, with the texture mirrored on the right one so
+        // the vertices along the middle are shared by both handednesses
+        let vertices = vec![
+            0.0, 0.0, 0.0, //
+            1.0, 0.0, 0.0, //
+            2.0, 0.0, 0.0, //
+            0.0, 1.0, 0.0, //
+            1.0, 1.0, 0.0, //
+            2.0, 1.0, 0.0, //
+        ];
+        let indices = vec![0, 1, 4, 0, 4, 3, 1, 2, 5, 1, 5, 4];
+        let mut mesh = Mesh::new(vertices, indices);
+        mesh.texcoords = vec![0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 0.0, 1.0];
+        mesh.generate_missing_attributes();
+
+        assert_eq!(mesh.vertex_count(), 8);
+        assert!(mesh.validate().is_ok());
+        for (corner, &index) in mesh.indices.iter().enumerate() {
+            let tangent = &mesh.tangents[index as usize * 4..index as usize * 4 + 4];
+            // The texture runs backwards on the right, which also flips the handedness
+            let sign = if corner < 6 { 1.0 } else { -1.0 };
+            assert_close(glm::make_vec3(&tangent[..3]), glm::vec3(sign, 0.0, 0.0));
+            assert_eq!(tangent[3], sign, "at corner {}", corner);
+        }
+    }
+
+    #[test]
+    fn validate() {
+        assert_eq!(cube().validate(), Ok(()));
+
+        let mut mesh = cube();
+        mesh.indices[4] = 8;
+        assert_eq!(
+            mesh.validate(),
+            Err(vec![MeshIssue::IndexOutOfRange {
+                position: 4,
+                index: 8
+            }])
+        );
+
+        // Both a repeated corner and three corners in a row leave a triangle without area
+        let mut mesh = cube();
+        mesh.indices.extend([0, 0, 1, 0, 1, 1].iter());
+        mesh.vertices.extend([3.0, -1.0, -1.0].iter());
+        mesh.colors.extend([1.0; 4].iter());
+        mesh.indices.extend([0, 1, 8].iter());
+        mesh.index_count = mesh.indices.len() as i32;
+        assert_eq!(
+            mesh.validate(),
+            Err(vec![
+                MeshIssue::DegenerateTriangle { triangle: 12 },
+                MeshIssue::DegenerateTriangle { triangle: 13 },
+                MeshIssue::DegenerateTriangle { triangle: 14 },
+            ])
+        );
+
+        // Optional attributes can be left out, but not be partially there
+        let mut mesh = cube();
+        mesh.colors.truncate(28);
+        mesh.normals = vec![0.0; 3];
+        mesh.indices.pop();
+        assert_eq!(
+            mesh.validate(),
+            Err(vec![
+                MeshIssue::AttributeLengthMismatch {
+                    attribute: "colors",
+                    expected: 32,
+                    actual: 28
+                },
+                MeshIssue::AttributeLengthMismatch {
+                    attribute: "normals",
+                    expected: 24,
+                    actual: 3
+                },
+                MeshIssue::IndexCountMismatch {
+                    index_count: 36,
+                    index_length: 35
+                },
+                MeshIssue::IncompleteTriangle { index_length: 35 },
+            ])
+        );
+    }
+
+    #[test]
+    fn repair_on_load() {
+        let cube = cube();
+        let mut indices = cube.indices.clone();
+        indices.extend([0, 1, 8, 2, 2, 3].iter());
+        let loaded = tobj::Mesh {
+            positions: cube.vertices.clone(),
+            normals: vec![0.0; 6],
+            indices,
+            ..Default::default()
+        };
+
+        let mesh = Mesh::from(loaded, [1.0, 1.0, 1.0, 1.0]);
+        assert_eq!(mesh.validate(), Ok(()));
+        assert_eq!(mesh.indices.len(), cube.indices.len());
+        assert_eq!(mesh.normals.len(), mesh.vertices.len());
     }
 }
@@ -0,0 +1,140 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::material::Material;
+use crate::mesh::Mesh;
+
+/// Writes the given meshes as separate objects of an OBJ file, with their materials in
+/// an MTL file next to it. Vertex colors are written as the (widely supported) extra
+/// components of the vertex positions
+pub fn save(path: &str, parts: &[(Mesh, Material)]) -> io::Result<()> {
+    let mtl_path = Path::new(path).with_extension("mtl");
+    let mtl_name = mtl_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let mut mtl = BufWriter::new(File::create(&mtl_path)?);
+    for (i, (_, material)) in parts.iter().enumerate() {
+        let color = material.base_color;
+        writeln!(mtl, "newmtl material_{}", i)?;
+        writeln!(mtl, "Kd {} {} {}", color.x, color.y, color.z)?;
+        writeln!(mtl, "d {}", color.w)?;
+        writeln!(mtl)?;
+    }
+
+    let mut obj = BufWriter::new(File::create(path)?);
+    writeln!(obj, "mtllib {}", mtl_name)?;
+
+    // OBJ indices are global across the file and start at 1. Not every mesh has
+    // texture coordinates and normals, so each kind needs its own offset
+    let (mut offset, mut texcoord_offset, mut normal_offset) = (1, 1, 1);
+    for (i, (mesh, _)) in parts.iter().enumerate() {
+        let vertex_count = mesh.vertex_count();
+        let has_colors = mesh.colors.len() == vertex_count * 4;
+        let has_normals = mesh.normals.len() == vertex_count * 3;
+        let has_texcoords = mesh.texcoords.len() == vertex_count * 2;
+
+        writeln!(obj, "o object_{}", i)?;
+        writeln!(obj, "usemtl material_{}", i)?;
+        for vertex in 0..vertex_count {
+            let p = mesh.position(vertex);
+            if has_colors {
+                let c = &mesh.colors[vertex * 4..vertex * 4 + 3];
+                writeln!(obj, "v {} {} {} {} {} {}", p.x, p.y, p.z, c[0], c[1], c[2])?;
+            } else {
+                writeln!(obj, "v {} {} {}", p.x, p.y, p.z)?;
+            }
+        }
+        if has_texcoords {
+            for uv in mesh.texcoords.chunks_exact(2) {
+                writeln!(obj, "vt {} {}", uv[0], uv[1])?;
+            }
+        }
+        if has_normals {
+            for n in mesh.normals.chunks_exact(3) {
+                writeln!(obj, "vn {} {} {}", n[0], n[1], n[2])?;
+            }
+        }
+
+        for triangle in mesh.indices.chunks_exact(3) {
+            write!(obj, "f")?;
+            for &index in triangle {
+                let (v, vt, vn) = (
+                    index as usize + offset,
+                    index as usize + texcoord_offset,
+                    index as usize + normal_offset,
+                );
+                match (has_texcoords, has_normals) {
+                    (true, true) => write!(obj, " {}/{}/{}", v, vt, vn)?,
+                    (true, false) => write!(obj, " {}/{}", v, vt)?,
+                    (false, true) => write!(obj, " {}//{}", v, vn)?,
+                    (false, false) => write!(obj, " {}", v)?,
+                }
+            }
+            writeln!(obj)?;
+        }
+
+        offset += vertex_count;
+        if has_texcoords {
+            texcoord_offset += vertex_count;
+        }
+        if has_normals {
+            normal_offset += vertex_count;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut triangle = Mesh::new(
+            vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+            vec![0, 1, 2],
+        );
+        triangle.colors = vec![1.0, 0.0, 0.0, 1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0];
+        triangle.normals = vec![0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0];
+        let square = Mesh::new(
+            vec![2.0, 0.0, 0.0, 3.0, 0.0, 0.0, 3.0, 1.0, 0.0, 2.0, 1.0, 0.0],
+            vec![0, 1, 2, 0, 2, 3],
+        );
+        let mut material = Material::new();
+        material.base_color = glm::vec4(0.5, 0.25, 1.0, 0.75);
+        let parts = [(triangle, Material::new()), (square, material)];
+
+        let path = std::env::temp_dir().join("gloom_obj_round_trip.obj");
+        let path = path.to_str().unwrap();
+        save(path, &parts).unwrap();
+        let options = tobj::LoadOptions {
+            single_index: true,
+            ..Default::default()
+        };
+        let (models, materials) = tobj::load_obj(path, &options).unwrap();
+        let materials = materials.unwrap();
+
+        assert_eq!(models.len(), 2);
+        for (model, (mesh, material)) in models.iter().zip(parts.iter()) {
+            assert_eq!(model.mesh.positions, mesh.vertices);
+            assert_eq!(model.mesh.indices, mesh.indices);
+            assert_eq!(model.mesh.normals, mesh.normals);
+            let loaded = &materials[model.mesh.material_id.unwrap()];
+            assert_eq!(
+                loaded.diffuse,
+                [
+                    material.base_color.x,
+                    material.base_color.y,
+                    material.base_color.z
+                ]
+            );
+            assert_eq!(loaded.dissolve, material.base_color.w);
+        }
+        // Colors are written without their alpha
+        assert_eq!(models[0].mesh.vertex_color[3..6], [0.0, 1.0, 0.0]);
+    }
+}
@@ -0,0 +1,399 @@
+use std::ops::Range;
+
+use crate::mesh::Mesh;
+
+/// Size of the FIFO cache used when measuring how well a mesh uses the vertex cache.
+/// Roughly what older GPUs had, newer ones do better
+const MEASURED_CACHE_SIZE: usize = 16;
+
+// Tuning values from Tom Forsyth's "Linear-Speed Vertex Cache Optimisation"
+const FORSYTH_CACHE_SIZE: usize = 32;
+const CACHE_DECAY_POWER: f32 = 1.5;
+const LAST_TRIANGLE_SCORE: f32 = 0.75;
+const VALENCE_BOOST_SCALE: f32 = 2.0;
+const VALENCE_BOOST_POWER: f32 = 0.5;
+
+/// Allowed increase in ACMR when splitting triangles into clusters for overdraw sorting
+const OVERDRAW_THRESHOLD: f32 = 1.05;
+
+/// Results of `optimize`, given as average cache miss ratio (vertex shader invocations
+/// per triangle). Lower is better, 0.5 is the best possible for large regular grids
+#[derive(Debug)]
+pub struct OptimizationReport {
+    pub acmr_before: f32,
+    pub acmr_after: f32,
+}
+
+/// Simulates a FIFO vertex cache of the given size, returning which triangles missed
+/// the cache for all three of their vertices
+fn simulate_cache(indices: &[u32], cache_size: usize) -> (usize, Vec<bool>) {
+    let mut cache: std::collections::VecDeque<u32> = std::collections::VecDeque::new();
+    let mut misses = 0;
+    let mut full_misses = Vec::with_capacity(indices.len() / 3);
+
+    for triangle in indices.chunks_exact(3) {
+        let mut triangle_misses = 0;
+        for &index in triangle {
+            if !cache.contains(&index) {
+                cache.push_back(index);
+                if cache.len() > cache_size {
+                    cache.pop_front();
+                }
+                triangle_misses += 1;
+            }
+        }
+        misses += triangle_misses;
+        full_misses.push(triangle_misses == 3);
+    }
+
+    (misses, full_misses)
+}
+
+/// Average cache miss ratio of the given indices for a FIFO cache of the given size
+pub fn acmr(indices: &[u32], cache_size: usize) -> f32 {
+    let triangle_count = indices.len() / 3;
+    if triangle_count == 0 {
+        return 0.0;
+    }
+    simulate_cache(indices, cache_size).0 as f32 / triangle_count as f32
+}
+
+fn forsyth_vertex_score(cache_position: Option<usize>, remaining_valence: u32) -> f32 {
+    if remaining_valence == 0 {
+        return -1.0;
+    }
+
+    let cache_score = match cache_position {
+        None => 0.0,
+        // The vertices of the last triangle get a fixed score, so the next triangle
+        // doesn't just turn around and go back
+        Some(position) if position < 3 => LAST_TRIANGLE_SCORE,
+        Some(position) => {
+            let scale = 1.0 / (FORSYTH_CACHE_SIZE - 3) as f32;
+            (1.0 - (position - 3) as f32 * scale).powf(CACHE_DECAY_POWER)
+        }
+    };
+
+    // Boost vertices with few triangles left, to get rid of them quickly
+    let valence_boost = (remaining_valence as f32).powf(-VALENCE_BOOST_POWER);
+    cache_score + VALENCE_BOOST_SCALE * valence_boost
+}
+
+/// Reorders triangles to make good use of the post transform vertex cache, using
+/// Tom Forsyth's algorithm. Triangles keep their winding
+pub fn optimize_vertex_cache(indices: &[u32], vertex_count: usize) -> Vec<u32> {
+    let triangle_count = indices.len() / 3;
+
+    // Triangles using each vertex
+    let mut vertex_triangles: Vec<Vec<usize>> = vec![Vec::new(); vertex_count];
+    for (triangle, corners) in indices.chunks_exact(3).enumerate() {
+        for &index in corners {
+            vertex_triangles[index as usize].push(triangle);
+        }
+    }
+
+    let mut remaining_valence: Vec<u32> = vertex_triangles
+        .iter()
+        .map(|triangles| triangles.len() as u32)
+        .collect();
+    let mut cache_position: Vec<Option<usize>> = vec![None; vertex_count];
+    let mut vertex_scores: Vec<f32> = remaining_valence
+        .iter()
+        .map(|&valence| forsyth_vertex_score(None, valence))
+        .collect();
+    let triangle_score = |scores: &Vec<f32>, triangle: usize| -> f32 {
+        indices[triangle * 3..triangle * 3 + 3]
+            .iter()
+            .map(|&index| scores[index as usize])
+            .sum()
+    };
+
+    let mut emitted = vec![false; triangle_count];
+    let mut result = Vec::with_capacity(triangle_count * 3);
+    let mut cache: Vec<u32> = Vec::with_capacity(FORSYTH_CACHE_SIZE + 3);
+    let mut next_unemitted = 0;
+    let mut best: Option<usize> = None;
+
+    for _ in 0..triangle_count {
+        // Fall back to the next triangle in the original order if the cache has
+        // nothing to offer, which happens at the start and between disconnected parts
+        let triangle = match best {
+            Some(triangle) => triangle,
+            None => {
+                while emitted[next_unemitted] {
+                    next_unemitted += 1;
+                }
+                next_unemitted
+            }
+        };
+
+        emitted[triangle] = true;
+        let corners = &indices[triangle * 3..triangle * 3 + 3];
+        result.extend_from_slice(corners);
+
+        // Move the vertices of the triangle to the front of the (LRU) cache
+        for &index in corners.iter().rev() {
+            cache.retain(|&cached| cached != index);
+            cache.insert(0, index);
+            remaining_valence[index as usize] -= 1;
+        }
+        for &evicted in cache.iter().skip(FORSYTH_CACHE_SIZE) {
+            cache_position[evicted as usize] = None;
+            vertex_scores[evicted as usize] =
+                forsyth_vertex_score(None, remaining_valence[evicted as usize]);
+        }
+        cache.truncate(FORSYTH_CACHE_SIZE);
+
+        for (position, &index) in cache.iter().enumerate() {
+            let index = index as usize;
+            cache_position[index] = Some(position);
+            vertex_scores[index] = forsyth_vertex_score(Some(position), remaining_valence[index]);
+        }
+
+        // Only triangles touching the cache changed score, so the best one is among them
+        best = None;
+        let mut best_score = -1.0;
+        for &index in &cache {
+            for &candidate in &vertex_triangles[index as usize] {
+                if emitted[candidate] {
+                    continue;
+                }
+                let score = triangle_score(&vertex_scores, candidate);
+                if score > best_score {
+                    best_score = score;
+                    best = Some(candidate);
+                }
+            }
+        }
+    }
+
+    result
+}
+
+/// Reorders clusters of triangles so those facing outwards from the center of the mesh
+/// are drawn first, making them hide the rest. Clusters are picked so the vertex cache
+/// efficiency gets at most `threshold` times worse. Expects the indices to already be
+/// optimized for the vertex cache
+pub fn optimize_overdraw(indices: &[u32], vertices: &[f32], threshold: f32) -> Vec<u32> {
+    let triangle_count = indices.len() / 3;
+    if triangle_count == 0 {
+        return Vec::new();
+    }
+    let position = |index: u32| {
+        let i = index as usize * 3;
+        glm::vec3(vertices[i], vertices[i + 1], vertices[i + 2])
+    };
+
+    // Triangles where every vertex missed the cache are natural places to split, as the
+    // cache starts over there anyway
+    let (_, full_misses) = simulate_cache(indices, MEASURED_CACHE_SIZE);
+    let mut hard_boundaries: Vec<usize> = (0..triangle_count)
+        .filter(|&triangle| triangle == 0 || full_misses[triangle])
+        .collect();
+    hard_boundaries.push(triangle_count);
+
+    // Split those further wherever the cluster so far is about as cache efficient as
+    // the whole cluster is
+    let mut clusters: Vec<(usize, usize)> = Vec::new();
+    for bounds in hard_boundaries.windows(2) {
+        let (start, end) = (bounds[0], bounds[1]);
+        let limit = acmr(&indices[start * 3..end * 3], MEASURED_CACHE_SIZE) * threshold;
+        let mut cluster_start = start;
+        let mut cache: std::collections::VecDeque<u32> = std::collections::VecDeque::new();
+        let mut misses = 0;
+        for triangle in start..end {
+            for &index in &indices[triangle * 3..triangle * 3 + 3] {
+                if !cache.contains(&index) {
+                    cache.push_back(index);
+                    if cache.len() > MEASURED_CACHE_SIZE {
+                        cache.pop_front();
+                    }
+                    misses += 1;
+                }
+            }
+            let cluster_triangles = triangle + 1 - cluster_start;
+            if triangle + 1 < end && (misses as f32 / cluster_triangles as f32) <= limit {
+                clusters.push((cluster_start, triangle + 1));
+                cluster_start = triangle + 1;
+                misses = 0;
+                // Sorting moves clusters around, so each has to work with a cold cache
+                cache.clear();
+            }
+        }
+        clusters.push((cluster_start, end));
+    }
+
+    let mesh_center = indices
+        .iter()
+        .fold(glm::Vec3::zeros(), |acc, &index| acc + position(index))
+        / indices.len() as f32;
+
+    // Sort by how far out the cluster is along its own normal
+    let mut keyed: Vec<(f32, (usize, usize))> = clusters
+        .into_iter()
+        .map(|(start, end)| {
+            let mut center = glm::Vec3::zeros();
+            let mut normal = glm::Vec3::zeros();
+            for corners in indices[start * 3..end * 3].chunks_exact(3) {
+                let (a, b, c) = (
+                    position(corners[0]),
+                    position(corners[1]),
+                    position(corners[2]),
+                );
+                center += (a + b + c) / 3.0;
+                normal += glm::cross(&(b - a), &(c - a));
+            }
+            center /= (end - start) as f32;
+            let length = glm::length(&normal);
+            let key = if length > 0.0 {
+                glm::dot(&(center - mesh_center), &(normal / length))
+            } else {
+                0.0
+            };
+            (key, (start, end))
+        })
+        .collect();
+    keyed.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+
+    keyed
+        .iter()
+        .flat_map(|&(_, (start, end))| indices[start * 3..end * 3].iter().cloned())
+        .collect()
+}
+
+/// Reorders the vertices of the mesh in the order the triangles first use them, so
+/// they are fetched from memory mostly sequentially. Unused vertices are removed
+pub fn optimize_vertex_fetch(mesh: &mut Mesh) {
+    let mut remap: Vec<Option<u32>> = vec![None; mesh.vertex_count()];
+    let mut order: Vec<usize> = Vec::with_capacity(mesh.vertex_count());
+    for index in mesh.indices.iter_mut() {
+        let old = *index as usize;
+        *index = *remap[old].get_or_insert_with(|| {
+            order.push(old);
+            (order.len() - 1) as u32
+        });
+    }
+
+    let reorder = |values: &Vec<f32>, components: usize| -> Vec<f32> {
+        if values.len() != remap.len() * components {
+            return values.clone();
+        }
+        order
+            .iter()
+            .flat_map(|&old| values[old * components..(old + 1) * components].iter())
+            .cloned()
+            .collect()
+    };
+    mesh.vertices = reorder(&mesh.vertices, 3);
+    mesh.colors = reorder(&mesh.colors, 4);
+    mesh.normals = reorder(&mesh.normals, 3);
+    mesh.texcoords = reorder(&mesh.texcoords, 2);
+    mesh.tangents = reorder(&mesh.tangents, 4);
+}
+
+/// Runs all optimizations on the mesh. What gets drawn stays the same, only the
+/// order of triangles and vertices changes
+#[allow(dead_code)]
+pub fn optimize(mesh: &mut Mesh) -> OptimizationReport {
+    let everything = 0..mesh.indices.len();
+    optimize_ranges(mesh, &[everything])
+}
+
+/// Like `optimize`, but triangles are only reordered within each of the given ranges of
+/// indices, so parts of the mesh that are drawn on their own stay together
+pub fn optimize_ranges(mesh: &mut Mesh, ranges: &[Range<usize>]) -> OptimizationReport {
+    let acmr_before = acmr(&mesh.indices, MEASURED_CACHE_SIZE);
+
+    // The vertex cache optimization keeps state for every vertex it is given, so each
+    // range gets its vertices numbered from zero instead of using those of the mesh
+    let mut local_ids: Vec<Option<u32>> = vec![None; mesh.vertex_count()];
+    let mut global_ids: Vec<u32> = Vec::new();
+    for range in ranges {
+        let local_indices: Vec<u32> = mesh.indices[range.clone()]
+            .iter()
+            .map(|&index| {
+                *local_ids[index as usize].get_or_insert_with(|| {
+                    global_ids.push(index);
+                    global_ids.len() as u32 - 1
+                })
+            })
+            .collect();
+
+        let indices = optimize_vertex_cache(&local_indices, global_ids.len());
+        let indices: Vec<u32> = indices
+            .iter()
+            .map(|&index| global_ids[index as usize])
+            .collect();
+        let indices = optimize_overdraw(&indices, &mesh.vertices, OVERDRAW_THRESHOLD);
+        mesh.indices[range.clone()].copy_from_slice(&indices);
+
+        for &index in &global_ids {
+            local_ids[index as usize] = None;
+        }
+        global_ids.clear();
+    }
+    optimize_vertex_fetch(mesh);
+
+    OptimizationReport {
+        acmr_before,
+        acmr_after: acmr(&mesh.indices, MEASURED_CACHE_SIZE),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heightmap::{self, NoiseSettings};
+
+    /// Triangles of the mesh as corner positions, each starting at its smallest corner
+    /// so the winding is kept, in sorted order
+    fn triangle_set(mesh: &Mesh) -> Vec<[[u32; 3]; 3]> {
+        let mut triangles: Vec<[[u32; 3]; 3]> = mesh
+            .indices
+            .chunks_exact(3)
+            .map(|corners| {
+                let mut triangle = [[0; 3]; 3];
+                for (corner, &index) in triangle.iter_mut().zip(corners) {
+                    let p = mesh.position(index as usize);
+                    *corner = [p.x.to_bits(), p.y.to_bits(), p.z.to_bits()];
+                }
+                let first = (0..3).min_by_key(|&i| triangle[i]).unwrap();
+                triangle.rotate_left(first);
+                triangle
+            })
+            .collect();
+        triangles.sort_unstable();
+        triangles
+    }
+
+    #[test]
+    fn keeps_triangles() {
+        let mut mesh = heightmap::from_noise(33, 100.0, 10.0, &NoiseSettings::default());
+        let before = triangle_set(&mesh);
+        let report = optimize(&mut mesh);
+
+        assert_eq!(triangle_set(&mesh), before);
+        assert!(report.acmr_after <= report.acmr_before);
+        assert!(mesh.validate().is_ok());
+    }
+
+    #[test]
+    fn keeps_ranges() {
+        let mut mesh = heightmap::from_noise(33, 100.0, 10.0, &NoiseSettings::default());
+        let ranges: Vec<Range<usize>> = (0..mesh.indices.len())
+            .step_by(300)
+            .map(|start| start..(start + 300).min(mesh.indices.len()))
+            .collect();
+        let before: Vec<Vec<[[u32; 3]; 3]>> = ranges
+            .iter()
+            .map(|range| triangle_set(&mesh.submesh(&mesh.indices[range.clone()])))
+            .collect();
+        optimize_ranges(&mut mesh, &ranges);
+
+        for (range, before) in ranges.iter().zip(before) {
+            let part = mesh.submesh(&mesh.indices[range.clone()]);
+            assert_eq!(triangle_set(&part), before);
+        }
+        assert!(mesh.validate().is_ok());
+    }
+}
@@ -0,0 +1,462 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::mesh::Mesh;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    fn from_name(name: &str) -> Result<ScalarType, String> {
+        match name {
+            "char" | "int8" => Ok(ScalarType::I8),
+            "uchar" | "uint8" => Ok(ScalarType::U8),
+            "short" | "int16" => Ok(ScalarType::I16),
+            "ushort" | "uint16" => Ok(ScalarType::U16),
+            "int" | "int32" => Ok(ScalarType::I32),
+            "uint" | "uint32" => Ok(ScalarType::U32),
+            "float" | "float32" => Ok(ScalarType::F32),
+            "double" | "float64" => Ok(ScalarType::F64),
+            t => Err(format!("Unknown property type {}", t)),
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ScalarType::I8 | ScalarType::U8 => 1,
+            ScalarType::I16 | ScalarType::U16 => 2,
+            ScalarType::I32 | ScalarType::U32 | ScalarType::F32 => 4,
+            ScalarType::F64 => 8,
+        }
+    }
+
+    /// Largest value of integer types, used to normalize colors
+    fn max_value(self) -> f64 {
+        match self {
+            ScalarType::I8 => i8::MAX as f64,
+            ScalarType::U8 => u8::MAX as f64,
+            ScalarType::I16 => i16::MAX as f64,
+            ScalarType::U16 => u16::MAX as f64,
+            ScalarType::I32 => i32::MAX as f64,
+            ScalarType::U32 => u32::MAX as f64,
+            ScalarType::F32 | ScalarType::F64 => 1.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Property {
+    Scalar(String, ScalarType),
+    // Name, type of the length and type of the items
+    List(String, ScalarType, ScalarType),
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+/// Reads values from the body of the file, in whichever format it has
+struct Reader<'a> {
+    format: Format,
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn read(&mut self, scalar: ScalarType) -> Result<f64, String> {
+        if self.format == Format::Ascii {
+            return self.read_ascii();
+        }
+
+        let size = scalar.size();
+        if self.position + size > self.data.len() {
+            return Err("Unexpected end of file".to_string());
+        }
+        let mut bytes = [0u8; 8];
+        bytes[..size].copy_from_slice(&self.data[self.position..self.position + size]);
+        if self.format == Format::BinaryBigEndian {
+            bytes[..size].reverse();
+        }
+        self.position += size;
+
+        Ok(match scalar {
+            ScalarType::I8 => bytes[0] as i8 as f64,
+            ScalarType::U8 => bytes[0] as f64,
+            ScalarType::I16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            ScalarType::U16 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            ScalarType::I32 => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            ScalarType::U32 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            ScalarType::F32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            ScalarType::F64 => f64::from_le_bytes(bytes),
+        })
+    }
+
+    fn read_ascii(&mut self) -> Result<f64, String> {
+        while self.position < self.data.len() && self.data[self.position].is_ascii_whitespace() {
+            self.position += 1;
+        }
+        let start = self.position;
+        while self.position < self.data.len() && !self.data[self.position].is_ascii_whitespace() {
+            self.position += 1;
+        }
+        if start == self.position {
+            return Err("Unexpected end of file".to_string());
+        }
+
+        let token = String::from_utf8_lossy(&self.data[start..self.position]);
+        token
+            .parse::<f64>()
+            .map_err(|_| format!("Invalid number {}", token))
+    }
+}
+
+/// Parses the header, returning the format, elements and where the body starts
+fn parse_header(data: &[u8]) -> Result<(Format, Vec<Element>, usize), String> {
+    const END: &[u8] = b"end_header";
+    let end = data
+        .windows(END.len())
+        .position(|w| w == END)
+        .ok_or("Missing end_header")?;
+    // The body starts right after the line break following end_header
+    let mut body = end + END.len();
+    while body < data.len() && data[body] != b'\n' {
+        body += 1;
+    }
+    body += 1;
+
+    let header = String::from_utf8_lossy(&data[..end]);
+    let mut lines = header.lines().map(str::trim);
+    if lines.next() != Some("ply") {
+        return Err("Not a PLY file".to_string());
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", "ascii", _] => format = Some(Format::Ascii),
+            ["format", "binary_little_endian", _] => format = Some(Format::BinaryLittleEndian),
+            ["format", "binary_big_endian", _] => format = Some(Format::BinaryBigEndian),
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| format!("Invalid element count {}", count))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", length, item, name] => elements
+                .last_mut()
+                .ok_or("Property outside of element")?
+                .properties
+                .push(Property::List(
+                    name.to_string(),
+                    ScalarType::from_name(length)?,
+                    ScalarType::from_name(item)?,
+                )),
+            ["property", scalar, name] => elements
+                .last_mut()
+                .ok_or("Property outside of element")?
+                .properties
+                .push(Property::Scalar(
+                    name.to_string(),
+                    ScalarType::from_name(scalar)?,
+                )),
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => return Err(format!("Unexpected header line: {}", line)),
+        }
+    }
+
+    Ok((format.ok_or("Missing format")?, elements, body))
+}
+
+/// Parses an ASCII or binary PLY file. Faces are triangulated as fans, and per vertex
+/// colors, normals and texture coordinates are kept if present
+pub fn parse(data: &[u8]) -> Result<Mesh, String> {
+    let (format, elements, body) = parse_header(data)?;
+    let mut reader = Reader {
+        format,
+        data,
+        position: body,
+    };
+
+    let mut vertices: Vec<f32> = Vec::new();
+    let mut colors: Vec<f32> = Vec::new();
+    let mut normals: Vec<f32> = Vec::new();
+    let mut texcoords: Vec<f32> = Vec::new();
+    let mut indices: Vec<u32> = Vec::new();
+
+    for element in &elements {
+        let property_names: Vec<&str> = element
+            .properties
+            .iter()
+            .map(|p| match p {
+                Property::Scalar(name, _) | Property::List(name, _, _) => name.as_str(),
+            })
+            .collect();
+        let has = |names: &[&str]| names.iter().all(|n| property_names.contains(n));
+        let has_colors = has(&["red", "green", "blue"]);
+        let has_normals = has(&["nx", "ny", "nz"]);
+        let uv_names = [["u", "v"], ["s", "t"], ["texture_u", "texture_v"]]
+            .iter()
+            .find(|names| has(&names[..]))
+            .cloned();
+
+        for _ in 0..element.count {
+            let mut scalars: Vec<(&str, f64, ScalarType)> = Vec::new();
+            let mut lists: Vec<(&str, Vec<f64>)> = Vec::new();
+            for property in &element.properties {
+                match property {
+                    Property::Scalar(name, scalar) => {
+                        scalars.push((name, reader.read(*scalar)?, *scalar))
+                    }
+                    Property::List(name, length, item) => {
+                        let length = reader.read(*length)? as usize;
+                        let items =
+                            (0..length)
+                                .map(|_| reader.read(*item))
+                                .collect::<Result<Vec<f64>, String>>()?;
+                        lists.push((name, items));
+                    }
+                }
+            }
+
+            let get = |name: &str| scalars.iter().find(|(n, _, _)| *n == name);
+            let value = |name: &str| get(name).map(|&(_, v, _)| v as f32).unwrap_or(0.0);
+
+            match element.name.as_str() {
+                "vertex" => {
+                    vertices.extend([value("x"), value("y"), value("z")].iter());
+                    if has_normals {
+                        normals.extend([value("nx"), value("ny"), value("nz")].iter());
+                    }
+                    if let Some([u, v]) = uv_names {
+                        texcoords.extend([value(u), value(v)].iter());
+                    }
+                    if has_colors {
+                        let channel = |name: &str| match get(name) {
+                            Some(&(_, v, scalar)) => (v / scalar.max_value()) as f32,
+                            None => 1.0,
+                        };
+                        colors.extend(
+                            [
+                                channel("red"),
+                                channel("green"),
+                                channel("blue"),
+                                channel("alpha"),
+                            ]
+                            .iter(),
+                        );
+                    }
+                }
+                "face" => {
+                    let face = lists
+                        .iter()
+                        .find(|(name, _)| *name == "vertex_indices" || *name == "vertex_index")
+                        .ok_or("Face without vertex indices")?;
+                    // Vertices may come after the faces, so their count is checked at the end
+                    let corners = face
+                        .1
+                        .iter()
+                        .map(|&index| {
+                            if index >= 0.0 && index.fract() == 0.0 && index <= u32::MAX as f64 {
+                                Ok(index as u32)
+                            } else {
+                                Err(format!("Face index {} out of range", index))
+                            }
+                        })
+                        .collect::<Result<Vec<u32>, String>>()?;
+                    for i in 1..corners.len().saturating_sub(1) {
+                        indices.extend([corners[0], corners[i], corners[i + 1]].iter());
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let vertex_count = vertices.len() / 3;
+    if let Some(index) = indices
+        .iter()
+        .find(|&&index| index as usize >= vertex_count)
+    {
+        return Err(format!("Face index {} out of range", index));
+    }
+
+    let mut mesh = Mesh::new(vertices, indices);
+    if !colors.is_empty() {
+        mesh.colors = colors;
+    }
+    mesh.normals = normals;
+    mesh.texcoords = texcoords;
+    mesh.generate_missing_attributes();
+    Ok(mesh)
+}
+
+#[allow(dead_code)]
+pub fn load(path: &str) -> Mesh {
+    println!("Loading PLY model...");
+    let before = std::time::Instant::now();
+    let data = std::fs::read(path).unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e));
+    let mesh = parse(&data).unwrap_or_else(|e| panic!("Failed to load PLY model {}: {}", path, e));
+    let after = std::time::Instant::now();
+    println!(
+        "Done in {:.3}ms. Loaded {} points and {} triangles.",
+        after.duration_since(before).as_micros() as f32 / 1e3,
+        mesh.vertex_count(),
+        mesh.indices.len() / 3,
+    );
+    mesh
+}
+
+/// Writes the given mesh to a PLY file, either as text or little endian binary.
+/// Colors are stored as bytes, which is what most tools expect
+pub fn save(path: &str, mesh: &Mesh, binary: bool) -> io::Result<()> {
+    let vertex_count = mesh.vertex_count();
+    let has_colors = mesh.colors.len() == vertex_count * 4;
+    let has_normals = mesh.normals.len() == vertex_count * 3;
+    let has_texcoords = mesh.texcoords.len() == vertex_count * 2;
+
+    let mut file = BufWriter::new(File::create(path)?);
+    writeln!(file, "ply")?;
+    if binary {
+        writeln!(file, "format binary_little_endian 1.0")?;
+    } else {
+        writeln!(file, "format ascii 1.0")?;
+    }
+    writeln!(file, "element vertex {}", vertex_count)?;
+    for name in &["x", "y", "z"] {
+        writeln!(file, "property float {}", name)?;
+    }
+    if has_normals {
+        for name in &["nx", "ny", "nz"] {
+            writeln!(file, "property float {}", name)?;
+        }
+    }
+    if has_texcoords {
+        for name in &["s", "t"] {
+            writeln!(file, "property float {}", name)?;
+        }
+    }
+    if has_colors {
+        for name in &["red", "green", "blue", "alpha"] {
+            writeln!(file, "property uchar {}", name)?;
+        }
+    }
+    writeln!(file, "element face {}", mesh.indices.len() / 3)?;
+    writeln!(file, "property list uchar int vertex_indices")?;
+    writeln!(file, "end_header")?;
+
+    for vertex in 0..vertex_count {
+        let mut floats: Vec<f32> = mesh.vertices[vertex * 3..vertex * 3 + 3].to_vec();
+        if has_normals {
+            floats.extend(&mesh.normals[vertex * 3..vertex * 3 + 3]);
+        }
+        if has_texcoords {
+            floats.extend(&mesh.texcoords[vertex * 2..vertex * 2 + 2]);
+        }
+        let colors: Vec<u8> = if has_colors {
+            mesh.colors[vertex * 4..vertex * 4 + 4]
+                .iter()
+                .map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8)
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        if binary {
+            for value in &floats {
+                file.write_all(&value.to_le_bytes())?;
+            }
+            file.write_all(&colors)?;
+        } else {
+            let line: Vec<String> = floats
+                .iter()
+                .map(|v| v.to_string())
+                .chain(colors.iter().map(|c| c.to_string()))
+                .collect();
+            writeln!(file, "{}", line.join(" "))?;
+        }
+    }
+
+    for triangle in mesh.indices.chunks_exact(3) {
+        if binary {
+            file.write_all(&[3u8])?;
+            for &index in triangle {
+                file.write_all(&(index as i32).to_le_bytes())?;
+            }
+        } else {
+            writeln!(file, "3 {} {} {}", triangle[0], triangle[1], triangle[2])?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A unit square in the xy plane facing +z, with a different color per corner
+    fn square() -> Mesh {
+        let mut mesh = Mesh::new(
+            vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0],
+            vec![0, 1, 2, 0, 2, 3],
+        );
+        mesh.normals = [0.0, 0.0, 1.0].repeat(4);
+        mesh.colors = vec![
+            1.0, 0.0, 0.0, 1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0, 1.0, 0.0,
+        ];
+        mesh
+    }
+
+    #[test]
+    fn round_trip() {
+        let original = square();
+        for &binary in &[false, true] {
+            let path = std::env::temp_dir().join(format!("gloom-rs-test-{}.ply", binary));
+            let path = path.to_str().unwrap();
+            save(path, &original, binary).unwrap();
+            let loaded = parse(&std::fs::read(path).unwrap()).unwrap();
+            std::fs::remove_file(path).unwrap();
+
+            assert_eq!(loaded.vertices, original.vertices);
+            assert_eq!(loaded.normals, original.normals);
+            assert_eq!(loaded.colors, original.colors);
+            assert_eq!(loaded.indices, original.indices);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_face_indices() {
+        let header = "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\n\
+                      property float y\nproperty float z\nelement face 1\n\
+                      property list uchar int vertex_indices\nend_header\n\
+                      0 0 0\n1 0 0\n0 1 0\n";
+        for face in &["3 0 1 3", "3 0 -1 2", "3 0 1.5 2"] {
+            let file = format!("{}{}\n", header, face);
+            assert!(parse(file.as_bytes()).is_err(), "accepted {}", face);
+        }
+        let file = format!("{}3 0 1 2\n", header);
+        assert_eq!(parse(file.as_bytes()).unwrap().indices, vec![0, 1, 2]);
+    }
+}
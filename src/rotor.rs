@@ -0,0 +1,115 @@
+/// Fraction of full speed at which a rotor starts lifting the helicopter off the
+/// ground. Lift grows from there until full speed
+const LIFT_OFF: f32 = 0.6;
+
+/// How fast a rotor can spin, and how quickly it gets there, in radians per second
+/// (squared for the accelerations)
+#[derive(Clone, Copy, Debug)]
+pub struct RotorSettings {
+    pub max_speed: f32,
+    pub spin_up: f32,
+    pub spin_down: f32, // Usually lower than spin_up, as rotors coast down
+}
+
+/// A spinning rotor, speeding up and slowing down towards what the throttle asks for
+#[derive(Clone, Copy, Debug)]
+pub struct Rotor {
+    pub angle: f32,
+    pub speed: f32,
+    pub settings: RotorSettings,
+}
+
+impl Rotor {
+    /// Starts out at full speed
+    pub fn new(settings: RotorSettings) -> Self {
+        Rotor {
+            angle: 0.0,
+            speed: settings.max_speed,
+            settings,
+        }
+    }
+
+    /// Changes speed towards the throttle (0 for stopped, 1 for full speed) within the
+    /// acceleration limits, and turns the rotor
+    pub fn update(&mut self, throttle: f32, delta_time: f32) {
+        let target = throttle.clamp(0.0, 1.0) * self.settings.max_speed;
+        let previous = self.speed;
+        self.speed = if target > self.speed {
+            (self.speed + self.settings.spin_up * delta_time).min(target)
+        } else {
+            (self.speed - self.settings.spin_down * delta_time).max(target)
+        };
+        // Average of the speeds at either end of the step, so the angle doesn't lag
+        // while speeding up
+        let turn = 2.0 * std::f32::consts::PI;
+        self.angle = (self.angle + (previous + self.speed) / 2.0 * delta_time).rem_euclid(turn);
+    }
+
+    /// How much of its weight the rotor carries, from 0 on the ground to 1 in flight
+    pub fn lift(&self) -> f32 {
+        let fraction = self.speed / self.settings.max_speed;
+        ((fraction - LIFT_OFF) / (1.0 - LIFT_OFF)).clamp(0.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETTINGS: RotorSettings = RotorSettings {
+        max_speed: 10.0,
+        spin_up: 4.0,
+        spin_down: 2.0,
+    };
+
+    #[test]
+    fn spins_up_and_down() {
+        let mut rotor = Rotor::new(SETTINGS);
+        assert_eq!(rotor.speed, 10.0);
+
+        // Coasts down towards idle, and no further
+        rotor.update(0.3, 1.0);
+        assert_eq!(rotor.speed, 8.0);
+        rotor.update(0.3, 10.0);
+        assert_eq!(rotor.speed, 3.0);
+
+        // Stops entirely, and stays where it stopped
+        rotor.update(0.0, 10.0);
+        assert_eq!(rotor.speed, 0.0);
+        let angle = rotor.angle;
+        rotor.update(0.0, 1.0);
+        assert_eq!(rotor.angle, angle);
+
+        // Speeds up faster than it slows down, and never past full speed
+        rotor.update(1.0, 1.0);
+        assert_eq!(rotor.speed, 4.0);
+        rotor.update(2.0, 10.0);
+        assert_eq!(rotor.speed, 10.0);
+    }
+
+    #[test]
+    fn turns_by_average_speed() {
+        let mut rotor = Rotor::new(SETTINGS);
+        rotor.speed = 0.0;
+        rotor.update(1.0, 0.5);
+        // From 0 to 2 radians per second over half a second
+        assert!((rotor.angle - 0.5).abs() < 1e-6);
+
+        for _ in 0..100 {
+            rotor.update(1.0, 0.37);
+            assert!(rotor.angle >= 0.0 && rotor.angle < 2.0 * std::f32::consts::PI);
+        }
+    }
+
+    #[test]
+    fn lift() {
+        let mut rotor = Rotor::new(SETTINGS);
+        assert_eq!(rotor.lift(), 1.0);
+        rotor.speed = SETTINGS.max_speed * LIFT_OFF;
+        assert_eq!(rotor.lift(), 0.0);
+        rotor.speed = 0.0;
+        assert_eq!(rotor.lift(), 0.0);
+        rotor.speed = SETTINGS.max_speed * (LIFT_OFF + 1.0) / 2.0;
+        assert!((rotor.lift() - 0.5).abs() < 1e-6);
+    }
+}
@@ -3,6 +3,11 @@ extern crate nalgebra_glm as glm;
 use std::mem::ManuallyDrop;
 use std::pin::Pin;
 
+use std::collections::HashMap;
+
+use crate::material::Material;
+use crate::mesh::Mesh;
+
 // Used to create an unholy abomination upon which you should not cast your gaze. This ended up
 // being a necessity due to wanting to keep the code written by students as "straight forward" as
 // possible. It is very very double plus ungood Rust, and intentionally leaks memory like a sieve.
@@ -16,6 +21,21 @@ use std::pin::Pin;
 // having what I arbitrarily decided to be the required level of "simplicity of use".
 pub type Node = ManuallyDrop<Pin<Box<SceneNode>>>;
 
+/// A simpler version of what a node draws, used once the node covers less than
+/// `screen_size` of the screen height
+#[derive(Clone, Copy, Debug)]
+pub struct LodLevel {
+    pub vao_id: u32,
+    pub index_count: i32,
+    pub screen_size: f32,
+}
+
+/// Picks the coarsest of the levels, ordered from detailed to coarse, that may be used
+/// at the given size on screen. None means nothing simpler than the full mesh will do
+pub fn select_lod(lods: &[LodLevel], screen_size: f32) -> Option<&LodLevel> {
+    lods.iter().rev().find(|lod| screen_size < lod.screen_size)
+}
+
 pub struct SceneNode {
     pub position        : glm::Vec3,   // Where I am in relation to my parent
     pub rotation        : glm::Vec3,   // How I should be rotated
@@ -27,6 +47,9 @@ pub struct SceneNode {
     pub vao_id      : u32,             // What I should draw
     pub index_count : i32,             // How much of it I shall draw
     pub shader_id   : u32,
+    pub material    : Material,        // How I should look
+    pub lods        : Vec<LodLevel>,   // What I draw from afar, from detailed to coarse
+    pub bounding_sphere : glm::Vec4,   // Where my mesh is (xyz) and how large it is (w)
 
     pub children: Vec<*mut SceneNode>, // Those I command
 }
@@ -43,6 +66,9 @@ impl SceneNode {
             vao_id          : 0,
             shader_id       : 0,
             index_count     : -1,
+            material        : Material::new(),
+            lods            : vec![],
+            bounding_sphere : glm::zero(),
             children        : vec![],
         })))
     }
@@ -57,6 +83,9 @@ impl SceneNode {
             vao_id,
             index_count,
             shader_id,
+            material: Material::new(),
+            lods: vec![],
+            bounding_sphere: glm::zero(),
             children: vec![],
         })))
     }
@@ -65,6 +94,35 @@ impl SceneNode {
         self.children.push(child as *const SceneNode as *mut SceneNode)
     }
 
+    /// Picks what to draw given how large the node appears on screen, as a fraction
+    /// of the screen height. Returns the VAO and index count to use
+    pub fn select_lod(&self, screen_size: f32) -> (u32, i32) {
+        select_lod(&self.lods, screen_size)
+            .map_or((self.vao_id, self.index_count), |lod| (lod.vao_id, lod.index_count))
+    }
+
+    /// Where this node's origin is in world space, using the current transformation
+    pub fn world_position(&self) -> glm::Vec3 {
+        let m = &self.current_transformation_matrix;
+        glm::vec3(m[(0, 3)], m[(1, 3)], m[(2, 3)])
+    }
+
+    /// Bounding sphere of this node's mesh in world space, using the current
+    /// transformation. The radius is scaled by the largest scaling factor
+    pub fn world_bounding_sphere(&self) -> (glm::Vec3, f32) {
+        let m = &self.current_transformation_matrix;
+        let center = m * glm::vec4(
+            self.bounding_sphere.x,
+            self.bounding_sphere.y,
+            self.bounding_sphere.z,
+            1.0,
+        );
+        let scale = (0..3)
+            .map(|c| glm::length(&glm::vec3(m[(0, c)], m[(1, c)], m[(2, c)])))
+            .fold(0.0, f32::max);
+        (center.xyz(), self.bounding_sphere.w * scale)
+    }
+
     #[allow(dead_code)]
     pub fn get_child(&mut self, index: usize) -> &mut SceneNode {
         unsafe {
@@ -77,6 +135,44 @@ impl SceneNode {
         self.children.len()
     }
 
+    /// Collects the meshes drawn by this node and all of its children, with their
+    /// current transformation baked in. The given map tells which mesh each VAO holds.
+    /// Only the first `index_count` indices are drawn, so only those are collected.
+    /// Levels of detail depend on the camera, so the full mesh is used regardless
+    pub fn flatten(&self, meshes: &HashMap<u32, &Mesh>) -> Vec<(Mesh, Material)> {
+        let mut parts = Vec::new();
+        self.flatten_into(meshes, &mut parts);
+        parts
+    }
+
+    fn flatten_into(&self, meshes: &HashMap<u32, &Mesh>, parts: &mut Vec<(Mesh, Material)>) {
+        if let Some(mesh) = meshes.get(&self.vao_id) {
+            let count = (self.index_count.max(0) as usize).min(mesh.indices.len());
+            let drawn = mesh.submesh(&mesh.indices[..count]);
+            parts.push((drawn.transformed(&self.current_transformation_matrix), self.material));
+        }
+        for &child in &self.children {
+            unsafe {
+                (*child).flatten_into(meshes, parts);
+            }
+        }
+    }
+
+    /// Like `flatten`, but merges everything into a single mesh with the base color
+    /// of each material multiplied into the vertex colors
+    pub fn flatten_to_mesh(&self, meshes: &HashMap<u32, &Mesh>) -> Mesh {
+        let mut result = Mesh::new(Vec::new(), Vec::new());
+        for (mut mesh, material) in self.flatten(meshes) {
+            for color in mesh.colors.chunks_exact_mut(4) {
+                for (channel, factor) in color.iter_mut().zip(material.base_color.iter()) {
+                    *channel *= factor;
+                }
+            }
+            result.append(&mesh);
+        }
+        result
+    }
+
     #[allow(dead_code)]
     pub fn print(&self) {
         let m = self.current_transformation_matrix;
@@ -133,3 +229,40 @@ impl IndexMut<usize> for SceneNode {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flatten() {
+        let triangle = Mesh::new(
+            vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+            vec![0, 1, 2],
+        );
+        let mut meshes = HashMap::new();
+        meshes.insert(1, &triangle);
+
+        let mut parent = SceneNode::from_vao(1, 0, 3);
+        let mut child = SceneNode::from_vao(1, 0, 3);
+        child.material.base_color = glm::vec4(1.0, 0.0, 0.0, 1.0);
+        parent.add_child(&child);
+        // Set like update_node_transformations would, the child moving along with its parent
+        parent.current_transformation_matrix = glm::translation(&glm::vec3(10.0, 0.0, 0.0));
+        child.current_transformation_matrix =
+            parent.current_transformation_matrix * glm::translation(&glm::vec3(0.0, 5.0, 0.0));
+
+        let parts = parent.flatten(&meshes);
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].0.position(1), glm::vec3(11.0, 0.0, 0.0));
+        assert_eq!(parts[1].0.position(2), glm::vec3(10.0, 6.0, 0.0));
+        assert_eq!(parts[1].1.base_color, child.material.base_color);
+
+        // The child's red material ends up in its vertex colors
+        let merged = parent.flatten_to_mesh(&meshes);
+        assert_eq!(merged.indices, vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(merged.position(4), glm::vec3(11.0, 5.0, 0.0));
+        assert_eq!(&merged.colors[8..12], &[1.0, 1.0, 1.0, 1.0]);
+        assert_eq!(&merged.colors[12..16], &[1.0, 0.0, 0.0, 1.0]);
+    }
+}
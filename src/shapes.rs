@@ -1,3 +1,9 @@
+use std::collections::HashMap;
+
+use crate::mesh::{self, Mesh};
+
+const FULL_TURN: f32 = 2.0 * std::f32::consts::PI;
+
 /// Utility function for generating a single triangle
 #[allow(dead_code)]
 pub fn generate_triangle_at(x: f32, y: f32, z: f32, size: f32) -> Vec<f32> {
@@ -87,8 +93,8 @@ pub fn generate_spiral(
             0.0,
         ]);
 
-        radius = radius + r_inc;
-        width = width + w_inc;
+        radius += r_inc;
+        width += w_inc;
 
         let index = seg * 2;
 
@@ -101,3 +107,1073 @@ pub fn generate_spiral(
 
     (vert, indices)
 }
+
+/// Twice the signed area of the triangle, positive if it is counter clockwise
+fn cross(a: &glm::Vec2, b: &glm::Vec2, c: &glm::Vec2) -> f32 {
+    (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x)
+}
+
+/// Twice the signed area of the polygon, positive if it is counter clockwise
+fn signed_area(points: &[glm::Vec2], ring: &[usize]) -> f32 {
+    (0..ring.len())
+        .map(|i| {
+            let (a, b) = (points[ring[i]], points[ring[(i + 1) % ring.len()]]);
+            a.x * b.y - b.x * a.y
+        })
+        .sum()
+}
+
+/// Whether two edges of the closed outline cross each other. Edges only touching at
+/// their ends don't count
+fn intersects_itself(outline: &[glm::Vec2]) -> bool {
+    let count = outline.len();
+    let edge = |i: usize| (&outline[i], &outline[(i + 1) % count]);
+    (0..count).any(|i| {
+        // Neighbouring edges share a corner, so start two edges along
+        (i + 2..count).any(|j| {
+            let ((a, b), (c, d)) = (edge(i), edge(j));
+            cross(a, b, c) * cross(a, b, d) < 0.0 && cross(c, d, a) * cross(c, d, b) < 0.0
+        })
+    })
+}
+
+fn in_triangle(p: &glm::Vec2, a: &glm::Vec2, b: &glm::Vec2, c: &glm::Vec2) -> bool {
+    cross(a, b, p) >= 0.0 && cross(b, c, p) >= 0.0 && cross(c, a, p) >= 0.0
+}
+
+/// Whether going from the vertex at `at` towards `target` starts out inside the
+/// (counter clockwise) polygon
+fn locally_inside(points: &[glm::Vec2], ring: &[usize], at: usize, target: &glm::Vec2) -> bool {
+    let count = ring.len();
+    let prev = &points[ring[(at + count - 1) % count]];
+    let here = &points[ring[at]];
+    let next = &points[ring[(at + 1) % count]];
+    if cross(prev, here, next) >= 0.0 {
+        cross(prev, here, target) >= 0.0 && cross(here, next, target) >= 0.0
+    } else {
+        cross(prev, here, target) >= 0.0 || cross(here, next, target) >= 0.0
+    }
+}
+
+/// Cuts the polygon open to the hole and walks around it, turning both into a single
+/// outline. The hole should be clockwise
+fn bridge_hole(points: &[glm::Vec2], ring: &mut Vec<usize>, hole: &[usize]) {
+    // Cast a ray to the right from the rightmost point of the hole, and find the
+    // closest edge it hits
+    let start = (0..hole.len())
+        .max_by(|&a, &b| {
+            points[hole[a]]
+                .x
+                .partial_cmp(&points[hole[b]].x)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        })
+        .unwrap();
+    let m = points[hole[start]];
+    let mut closest: Option<(f32, usize)> = None;
+    for i in 0..ring.len() {
+        let (a, b) = (points[ring[i]], points[ring[(i + 1) % ring.len()]]);
+        if (a.y > m.y) == (b.y > m.y) {
+            continue;
+        }
+        let x = a.x + (m.y - a.y) * (b.x - a.x) / (b.y - a.y);
+        if x >= m.x && closest.is_none_or(|(best, _)| x < best) {
+            // The end of the edge furthest right is the candidate to connect to
+            let end = if a.x > b.x { i } else { (i + 1) % ring.len() };
+            closest = Some((x, end));
+        }
+    }
+    let (hit_x, mut target) = match closest {
+        Some(closest) => closest,
+        // Not inside the polygon at all, so there is nothing to connect it to
+        None => return,
+    };
+
+    // Some other vertex might be in the way between the ray and the candidate. If so,
+    // connect to the one closest in angle to the ray instead
+    let hit = glm::vec2(hit_x, m.y);
+    let candidate = points[ring[target]];
+    let mut best_tan = f32::INFINITY;
+    for i in 0..ring.len() {
+        let p = points[ring[i]];
+        if p.x < m.x || ring[i] == ring[target] {
+            continue;
+        }
+        // Which way around the triangle goes depends on the side of the ray the
+        // candidate is on, so try both
+        let inside = in_triangle(&p, &m, &hit, &candidate) || in_triangle(&p, &m, &candidate, &hit);
+        if !inside || !locally_inside(points, ring, i, &m) {
+            continue;
+        }
+        let tan = (p.y - m.y).abs() / (p.x - m.x).max(f32::EPSILON);
+        if tan < best_tan {
+            best_tan = tan;
+            target = i;
+        }
+    }
+
+    // The same point may appear more than once after earlier bridges, so pick the
+    // copy whose corner the hole is actually in
+    let point = ring[target];
+    if let Some(copy) =
+        (0..ring.len()).find(|&i| ring[i] == point && locally_inside(points, ring, i, &m))
+    {
+        target = copy;
+    }
+
+    let mut bridged: Vec<usize> = Vec::with_capacity(ring.len() + hole.len() + 2);
+    bridged.extend_from_slice(&ring[..=target]);
+    bridged.extend((0..=hole.len()).map(|i| hole[(start + i) % hole.len()]));
+    bridged.extend_from_slice(&ring[target..]);
+    *ring = bridged;
+}
+
+/// Utility function for triangulating a simple polygon with holes by ear clipping.
+/// The vertices are the outline followed by each hole, at z = 0, and the triangles
+/// are counter clockwise whichever way the outline goes. Holes must be inside the
+/// outline and not touch each other. Fails if the outline intersects itself, or if
+/// there is no way to clip the polygon into triangles
+#[allow(dead_code)]
+pub fn generate_polygon(
+    outline: &[glm::Vec2],
+    holes: &[Vec<glm::Vec2>],
+) -> Result<(Vec<f32>, Vec<u32>), String> {
+    let points: Vec<glm::Vec2> = outline
+        .iter()
+        .chain(holes.iter().flatten())
+        .cloned()
+        .collect();
+    let vertices: Vec<f32> = points.iter().flat_map(|p| [p.x, p.y, 0.0]).collect();
+    if outline.len() < 3 {
+        return Ok((vertices, Vec::new()));
+    }
+    if intersects_itself(outline) {
+        return Err("Polygon outline intersects itself".to_string());
+    }
+
+    // Make the outline counter clockwise and the holes clockwise
+    let mut ring: Vec<usize> = (0..outline.len()).collect();
+    if signed_area(&points, &ring) < 0.0 {
+        ring.reverse();
+    }
+    let mut hole_rings: Vec<Vec<usize>> = Vec::new();
+    let mut offset = outline.len();
+    for hole in holes {
+        let mut hole_ring: Vec<usize> = (offset..offset + hole.len()).collect();
+        offset += hole.len();
+        if hole.len() < 3 {
+            continue;
+        }
+        if signed_area(&points, &hole_ring) > 0.0 {
+            hole_ring.reverse();
+        }
+        hole_rings.push(hole_ring);
+    }
+
+    // Bridge the holes from right to left, so earlier bridges don't block later ones
+    let rightmost = |hole: &Vec<usize>| {
+        hole.iter()
+            .map(|&i| points[i].x)
+            .fold(f32::NEG_INFINITY, f32::max)
+    };
+    hole_rings.sort_by(|a, b| {
+        rightmost(b)
+            .partial_cmp(&rightmost(a))
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    for hole in &hole_rings {
+        bridge_hole(&points, &mut ring, hole);
+    }
+
+    let mut indices: Vec<u32> = Vec::with_capacity((ring.len() - 2) * 3);
+    let mut stuck = false;
+    while ring.len() > 3 {
+        let count = ring.len();
+        let corners = |i: usize| {
+            (
+                ring[(i + count - 1) % count],
+                ring[i],
+                ring[(i + 1) % count],
+            )
+        };
+        let is_ear = |i: usize| {
+            let (a, b, c) = corners(i);
+            let (pa, pb, pc) = (points[a], points[b], points[c]);
+            if cross(&pa, &pb, &pc) <= 0.0 {
+                return false;
+            }
+            // Points shared with the corners, like the ends of bridges, don't count
+            ring.iter().all(|&other| {
+                let p = points[other];
+                p == pa || p == pb || p == pc || !in_triangle(&p, &pa, &pb, &pc)
+            })
+        };
+
+        // No proper ears means the outline is degenerate somewhere. Drop points
+        // without any area first, and as a last resort clip any convex corner
+        let ear = (0..count)
+            .find(|&i| is_ear(i))
+            .or_else(|| {
+                (0..count).find(|&i| {
+                    let (a, b, c) = corners(i);
+                    cross(&points[a], &points[b], &points[c]) == 0.0
+                })
+            })
+            .or_else(|| {
+                stuck = true;
+                (0..count).find(|&i| {
+                    let (a, b, c) = corners(i);
+                    cross(&points[a], &points[b], &points[c]) > 0.0
+                })
+            });
+        let ear = match ear {
+            Some(ear) => ear,
+            None => break,
+        };
+
+        let (a, b, c) = corners(ear);
+        if cross(&points[a], &points[b], &points[c]) > 0.0 {
+            indices.extend([a as u32, b as u32, c as u32]);
+        }
+        ring.remove(ear);
+    }
+    if ring.len() == 3 && cross(&points[ring[0]], &points[ring[1]], &points[ring[2]]) > 0.0 {
+        indices.extend(ring.iter().map(|&i| i as u32));
+    }
+    if stuck {
+        return Err("Polygon has no ears left to clip".to_string());
+    }
+
+    Ok((vertices, indices))
+}
+
+/// A circle of vertices around the y axis, for building surfaces of revolution
+struct Ring {
+    radius: f32,
+    y: f32,
+    normal: glm::Vec2, // Pointing out from the axis (x) and up (y)
+    v: f32,
+}
+
+/// Makes a mesh out of the given vertex attributes and triangles, leaving out triangles
+/// without any area (like those touching the poles of a sphere)
+fn build_mesh(
+    vertices: Vec<f32>,
+    normals: Vec<f32>,
+    texcoords: Vec<f32>,
+    triangles: Vec<[u32; 3]>,
+) -> Mesh {
+    let position = |index: u32| {
+        let i = index as usize * 3;
+        glm::vec3(vertices[i], vertices[i + 1], vertices[i + 2])
+    };
+    let indices: Vec<u32> = triangles
+        .iter()
+        .filter(|triangle| {
+            let (a, b, c) = (
+                position(triangle[0]),
+                position(triangle[1]),
+                position(triangle[2]),
+            );
+            let longest = glm::distance(&a, &b)
+                .max(glm::distance(&b, &c))
+                .max(glm::distance(&c, &a));
+            glm::length(&glm::cross(&(b - a), &(c - a))) > longest * longest * 1e-6
+        })
+        .flat_map(|triangle| triangle.iter().cloned())
+        .collect();
+
+    let mut mesh = Mesh::new(vertices, indices);
+    mesh.normals = normals;
+    mesh.texcoords = texcoords;
+    mesh.generate_missing_attributes();
+    mesh
+}
+
+/// Sweeps the rings `angle` radians around the y axis in `segments` steps, connecting
+/// each ring to the next. Rings going from the bottom to the top face away from the axis,
+/// and consecutive rings at the same place make a hard edge
+fn generate_revolution(rings: &[Ring], segments: u32, angle: f32) -> Mesh {
+    let mut vertices = Vec::new();
+    let mut normals = Vec::new();
+    let mut texcoords = Vec::new();
+    let mut triangles = Vec::new();
+    let columns = segments + 1;
+
+    for (i, ring) in rings.iter().enumerate() {
+        // The first and last column are at the same place, but need different
+        // texture coordinates
+        for segment in 0..columns {
+            let u = segment as f32 / segments as f32;
+            let (sin, cos) = (u * angle).sin_cos();
+            vertices.extend([ring.radius * sin, ring.y, ring.radius * cos]);
+            normals.extend([ring.normal.x * sin, ring.normal.y, ring.normal.x * cos]);
+            texcoords.extend([u, ring.v]);
+        }
+
+        if i == 0 {
+            continue;
+        }
+        let previous = &rings[i - 1];
+        if previous.radius == ring.radius && previous.y == ring.y {
+            continue;
+        }
+        let (below, above) = ((i as u32 - 1) * columns, i as u32 * columns);
+        for segment in 0..segments {
+            let (a, b) = (below + segment, below + segment + 1);
+            let (c, d) = (above + segment, above + segment + 1);
+            triangles.push([a, b, c]);
+            triangles.push([b, d, c]);
+        }
+    }
+
+    build_mesh(vertices, normals, texcoords, triangles)
+}
+
+/// Rings along a quarter circle around (0, y), from `from` to `to` radians above the
+/// horizontal, with texture coordinates from `v_from` to `v_to`
+fn arc_rings(
+    radius: f32,
+    y: f32,
+    from: f32,
+    to: f32,
+    steps: u32,
+    v_from: f32,
+    v_to: f32,
+) -> Vec<Ring> {
+    (0..=steps)
+        .map(|step| {
+            let t = step as f32 / steps as f32;
+            let (sin, cos) = (from + (to - from) * t).sin_cos();
+            Ring {
+                radius: radius * cos,
+                y: y + radius * sin,
+                normal: glm::vec2(cos, sin),
+                v: v_from + (v_to - v_from) * t,
+            }
+        })
+        .collect()
+}
+
+/// Rings for a flat cap at the given height, facing up or down. Texture coordinates
+/// go from the center to the rim
+fn cap_rings(radius: f32, y: f32, up: bool) -> Vec<Ring> {
+    let normal = glm::vec2(0.0, if up { 1.0 } else { -1.0 });
+    let center = Ring {
+        radius: 0.0,
+        y,
+        normal,
+        v: 0.0,
+    };
+    let rim = Ring {
+        radius,
+        y,
+        normal,
+        v: 1.0,
+    };
+    if up {
+        vec![rim, center]
+    } else {
+        vec![center, rim]
+    }
+}
+
+/// Utility function for generating a cube with the given side length, centered on
+/// the origin. Each face has its own vertices and the whole texture
+#[allow(dead_code)]
+pub fn generate_cube(size: f32) -> Mesh {
+    let half = size / 2.0;
+    // Normal, and the directions u and v go along the face
+    let faces = [
+        (
+            glm::vec3(1.0, 0.0, 0.0),
+            glm::vec3(0.0, 0.0, -1.0),
+            glm::vec3(0.0, 1.0, 0.0),
+        ),
+        (
+            glm::vec3(-1.0, 0.0, 0.0),
+            glm::vec3(0.0, 0.0, 1.0),
+            glm::vec3(0.0, 1.0, 0.0),
+        ),
+        (
+            glm::vec3(0.0, 1.0, 0.0),
+            glm::vec3(1.0, 0.0, 0.0),
+            glm::vec3(0.0, 0.0, -1.0),
+        ),
+        (
+            glm::vec3(0.0, -1.0, 0.0),
+            glm::vec3(1.0, 0.0, 0.0),
+            glm::vec3(0.0, 0.0, 1.0),
+        ),
+        (
+            glm::vec3(0.0, 0.0, 1.0),
+            glm::vec3(1.0, 0.0, 0.0),
+            glm::vec3(0.0, 1.0, 0.0),
+        ),
+        (
+            glm::vec3(0.0, 0.0, -1.0),
+            glm::vec3(-1.0, 0.0, 0.0),
+            glm::vec3(0.0, 1.0, 0.0),
+        ),
+    ];
+
+    let mut vertices = Vec::new();
+    let mut normals = Vec::new();
+    let mut texcoords = Vec::new();
+    let mut triangles = Vec::new();
+    for (face, (normal, u, v)) in faces.iter().enumerate() {
+        for &(s, t) in &[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)] {
+            let corner = (normal + u * (s * 2.0 - 1.0) + v * (t * 2.0 - 1.0)) * half;
+            vertices.extend(corner.iter());
+            normals.extend(normal.iter());
+            texcoords.extend([s, t]);
+        }
+        let first = face as u32 * 4;
+        triangles.push([first, first + 1, first + 2]);
+        triangles.push([first, first + 2, first + 3]);
+    }
+
+    build_mesh(vertices, normals, texcoords, triangles)
+}
+
+/// Utility function for generating a sphere out of `segments` slices around the y axis
+/// and `rings` stacks from pole to pole
+#[allow(dead_code)]
+pub fn generate_uv_sphere(radius: f32, segments: u32, rings: u32) -> Mesh {
+    let half_pi = std::f32::consts::FRAC_PI_2;
+    generate_revolution(
+        &arc_rings(radius, 0.0, -half_pi, half_pi, rings.max(2), 0.0, 1.0),
+        segments.max(3),
+        FULL_TURN,
+    )
+}
+
+/// Utility function for generating a sphere by subdividing an icosahedron, which
+/// spreads the triangles more evenly than a UV sphere
+#[allow(dead_code)]
+pub fn generate_icosphere(radius: f32, subdivisions: u32) -> Mesh {
+    let t = (1.0 + 5.0f32.sqrt()) / 2.0;
+    let mut points: Vec<glm::Vec3> = [
+        (-1.0, t, 0.0),
+        (1.0, t, 0.0),
+        (-1.0, -t, 0.0),
+        (1.0, -t, 0.0),
+        (0.0, -1.0, t),
+        (0.0, 1.0, t),
+        (0.0, -1.0, -t),
+        (0.0, 1.0, -t),
+        (t, 0.0, -1.0),
+        (t, 0.0, 1.0),
+        (-t, 0.0, -1.0),
+        (-t, 0.0, 1.0),
+    ]
+    .iter()
+    .map(|&(x, y, z)| glm::normalize(&glm::vec3(x, y, z)))
+    .collect();
+    let mut triangles: Vec<[u32; 3]> = vec![
+        [0, 11, 5],
+        [0, 5, 1],
+        [0, 1, 7],
+        [0, 7, 10],
+        [0, 10, 11],
+        [1, 5, 9],
+        [5, 11, 4],
+        [11, 10, 2],
+        [10, 7, 6],
+        [7, 1, 8],
+        [3, 9, 4],
+        [3, 4, 2],
+        [3, 2, 6],
+        [3, 6, 8],
+        [3, 8, 9],
+        [4, 9, 5],
+        [2, 4, 11],
+        [6, 2, 10],
+        [8, 6, 7],
+        [9, 8, 1],
+    ];
+
+    // Split every triangle into four, sharing the new points along each edge
+    for _ in 0..subdivisions {
+        let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut midpoint = |a: u32, b: u32| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                let point = (points[a as usize] + points[b as usize]) / 2.0;
+                points.push(glm::normalize(&point));
+                points.len() as u32 - 1
+            })
+        };
+        triangles = triangles
+            .iter()
+            .flat_map(|&[a, b, c]| {
+                let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+                vec![[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    // Wrap the texture around like on a UV sphere
+    let mut texcoords: Vec<glm::Vec2> = points
+        .iter()
+        .map(|p| {
+            glm::vec2(
+                0.5 + p.x.atan2(p.z) / (2.0 * std::f32::consts::PI),
+                0.5 + p.y.asin() / std::f32::consts::PI,
+            )
+        })
+        .collect();
+    // Triangles across the seam would squeeze the whole texture in between their
+    // corners, so give them copies of the corners on the far side of the texture
+    let mut wrapped: HashMap<u32, u32> = HashMap::new();
+    for triangle in triangles.iter_mut() {
+        let us: Vec<f32> = triangle.iter().map(|&i| texcoords[i as usize].x).collect();
+        let (lowest, highest) = (
+            us.iter().cloned().fold(f32::INFINITY, f32::min),
+            us.iter().cloned().fold(f32::NEG_INFINITY, f32::max),
+        );
+        if highest - lowest <= 0.5 {
+            continue;
+        }
+        for corner in triangle.iter_mut() {
+            if texcoords[*corner as usize].x < 0.5 {
+                *corner = *wrapped.entry(*corner).or_insert_with(|| {
+                    points.push(points[*corner as usize]);
+                    texcoords.push(texcoords[*corner as usize] + glm::vec2(1.0, 0.0));
+                    points.len() as u32 - 1
+                });
+            }
+        }
+    }
+
+    build_mesh(
+        points
+            .iter()
+            .flat_map(|p| [p.x * radius, p.y * radius, p.z * radius])
+            .collect(),
+        points.iter().flat_map(|p| [p.x, p.y, p.z]).collect(),
+        texcoords.iter().flat_map(|uv| [uv.x, uv.y]).collect(),
+        triangles,
+    )
+}
+
+/// Utility function for generating a closed cylinder along the y axis, centered on
+/// the origin
+#[allow(dead_code)]
+pub fn generate_cylinder(radius: f32, height: f32, segments: u32) -> Mesh {
+    let half = height / 2.0;
+    let side = glm::vec2(1.0, 0.0);
+    let mut rings = cap_rings(radius, -half, false);
+    rings.push(Ring {
+        radius,
+        y: -half,
+        normal: side,
+        v: 0.0,
+    });
+    rings.push(Ring {
+        radius,
+        y: half,
+        normal: side,
+        v: 1.0,
+    });
+    rings.extend(cap_rings(radius, half, true));
+    generate_revolution(&rings, segments.max(3), FULL_TURN)
+}
+
+/// Utility function for generating a cone along the y axis with its base at the bottom,
+/// centered on the origin
+#[allow(dead_code)]
+pub fn generate_cone(radius: f32, height: f32, segments: u32) -> Mesh {
+    let half = height / 2.0;
+    let side = glm::normalize(&glm::vec2(height, radius));
+    let mut rings = cap_rings(radius, -half, false);
+    rings.push(Ring {
+        radius,
+        y: -half,
+        normal: side,
+        v: 0.0,
+    });
+    rings.push(Ring {
+        radius: 0.0,
+        y: half,
+        normal: side,
+        v: 1.0,
+    });
+    generate_revolution(&rings, segments.max(3), FULL_TURN)
+}
+
+/// Utility function for generating a torus around the y axis. `segments` goes around
+/// the y axis and `sides` around the tube
+#[allow(dead_code)]
+pub fn generate_torus(major_radius: f32, minor_radius: f32, segments: u32, sides: u32) -> Mesh {
+    let sides = sides.max(3);
+    let rings: Vec<Ring> = (0..=sides)
+        .map(|side| {
+            let v = side as f32 / sides as f32;
+            let (sin, cos) = (v * 2.0 * std::f32::consts::PI).sin_cos();
+            Ring {
+                radius: major_radius + minor_radius * cos,
+                y: minor_radius * sin,
+                normal: glm::vec2(cos, sin),
+                v,
+            }
+        })
+        .collect();
+    generate_revolution(&rings, segments.max(3), FULL_TURN)
+}
+
+/// Utility function for generating a flat grid in the xz plane facing up, centered on
+/// the origin, with `columns` cells along x and `rows` along z
+#[allow(dead_code)]
+pub fn generate_plane(width: f32, depth: f32, columns: u32, rows: u32) -> Mesh {
+    let (columns, rows) = (columns.max(1), rows.max(1));
+    let mut vertices = Vec::new();
+    let mut normals = Vec::new();
+    let mut texcoords = Vec::new();
+    let mut triangles = Vec::new();
+
+    for row in 0..=rows {
+        for column in 0..=columns {
+            let (u, v) = (column as f32 / columns as f32, row as f32 / rows as f32);
+            // v goes towards -z, so the texture is upright when seen from the front
+            vertices.extend([(u - 0.5) * width, 0.0, (0.5 - v) * depth]);
+            normals.extend([0.0, 1.0, 0.0]);
+            texcoords.extend([u, v]);
+        }
+    }
+    for row in 0..rows {
+        for column in 0..columns {
+            let a = row * (columns + 1) + column;
+            let (b, c) = (a + 1, a + columns + 1);
+            triangles.push([a, b, c]);
+            triangles.push([b, c + 1, c]);
+        }
+    }
+
+    build_mesh(vertices, normals, texcoords, triangles)
+}
+
+/// Utility function for generating a capsule along the y axis, centered on the origin.
+/// The height includes the rounded ends, and `rings` is the number of rings in each end
+#[allow(dead_code)]
+pub fn generate_capsule(radius: f32, height: f32, segments: u32, rings: u32) -> Mesh {
+    let half_pi = std::f32::consts::FRAC_PI_2;
+    let half = (height / 2.0 - radius).max(0.0);
+    // Spread the texture along the outline, so it doesn't stretch on the straight part
+    let cap_length = half_pi * radius;
+    let total = 2.0 * cap_length + 2.0 * half;
+    let (cap_v, side_v) = (cap_length / total, (cap_length + 2.0 * half) / total);
+
+    let mut outline = arc_rings(radius, -half, -half_pi, 0.0, rings.max(1), 0.0, cap_v);
+    outline.extend(arc_rings(
+        radius,
+        half,
+        0.0,
+        half_pi,
+        rings.max(1),
+        side_v,
+        1.0,
+    ));
+    generate_revolution(&outline, segments.max(3), FULL_TURN)
+}
+
+/// A point along a 2D outline, with the direction the outline goes there and how far
+/// along the outline it is, from 0 to 1
+struct OutlinePoint {
+    position: glm::Vec2,
+    tangent: glm::Vec2,
+    distance: f32,
+}
+
+/// Walks along the outline, smoothing the direction at gentle corners and doubling up
+/// points at sharp ones. Closed outlines end with a copy of the first point
+fn trace_outline(points: &[glm::Vec2], closed: bool) -> Vec<OutlinePoint> {
+    let count = points.len();
+    let crease_cos = mesh::DEFAULT_CREASE_ANGLE.cos();
+    let direction = |from: usize, to: usize| {
+        let d = points[to % count] - points[from % count];
+        if glm::length(&d) > 0.0 {
+            glm::normalize(&d)
+        } else {
+            d
+        }
+    };
+
+    let last = if closed { count } else { count - 1 };
+    let mut distance = 0.0;
+    let mut traced = Vec::new();
+    for i in 0..=last {
+        if i > 0 {
+            distance += glm::distance(&points[(i - 1) % count], &points[i % count]);
+        }
+        let has_before = closed || i > 0;
+        let has_after = closed || i < count - 1;
+        let before = if has_before {
+            direction(i + count - 1, i)
+        } else {
+            direction(i, i + 1)
+        };
+        let after = if has_after {
+            direction(i, i + 1)
+        } else {
+            before
+        };
+
+        let point = |tangent: glm::Vec2| OutlinePoint {
+            position: points[i % count],
+            tangent,
+            distance,
+        };
+        if glm::dot(&before, &after) >= crease_cos {
+            let smooth = before + after;
+            traced.push(point(if glm::length(&smooth) > 0.0 {
+                glm::normalize(&smooth)
+            } else {
+                after
+            }));
+        } else {
+            // Where a closed outline starts, the corner is split between its two ends
+            if i != 0 {
+                traced.push(point(before));
+            }
+            if i != last || !closed {
+                traced.push(point(after));
+            }
+        }
+    }
+
+    let total = distance.max(f32::EPSILON);
+    for point in traced.iter_mut() {
+        point.distance /= total;
+    }
+    traced
+}
+
+/// Takes x and y of every vertex in a list of x, y, z positions, like the ones from
+/// `generate_circle` and `generate_spiral`, so they can be used as the shape of an
+/// extrusion or the profile of a lathe. The center of a circle is its first vertex, so
+/// leave it out with `&vertices[3..]`. Circles go around clockwise, so reverse them for
+/// extrusions to face outwards
+#[allow(dead_code)]
+pub fn outline_from_vertices(vertices: &[f32]) -> Vec<glm::Vec2> {
+    vertices
+        .chunks_exact(3)
+        .map(|vertex| glm::vec2(vertex[0], vertex[1]))
+        .collect()
+}
+
+/// Utility function for revolving a profile `angle` radians around the y axis, like on a
+/// lathe. The profile is given as (distance from the axis, height), and faces away from
+/// the axis where it goes upwards. Sharp corners in the profile stay sharp
+#[allow(dead_code)]
+pub fn generate_lathe(profile: &[glm::Vec2], closed: bool, segments: u32, angle: f32) -> Mesh {
+    if profile.len() < 2 {
+        return Mesh::new(Vec::new(), Vec::new());
+    }
+    let rings: Vec<Ring> = trace_outline(profile, closed)
+        .iter()
+        .map(|point| Ring {
+            radius: point.position.x,
+            y: point.position.y,
+            normal: glm::vec2(point.tangent.y, -point.tangent.x),
+            v: point.distance,
+        })
+        .collect();
+    generate_revolution(&rings, segments.max(1), angle)
+}
+
+/// How the shape changes as it is extruded along a path
+#[derive(Clone, Copy, Debug)]
+pub struct ExtrudeOptions {
+    pub twist: f32, // Radians the shape turns for each point along the path
+    pub scale: f32, // How much the shape grows for each point along the path
+    pub caps: bool, // Whether to close off the ends, if the shape is closed
+}
+
+impl Default for ExtrudeOptions {
+    fn default() -> Self {
+        ExtrudeOptions {
+            twist: 0.0,
+            scale: 1.0,
+            caps: true,
+        }
+    }
+}
+
+/// Frames (tangent, normal, binormal) along the path that twist as little as possible,
+/// so the extruded shape doesn't spin around on its own
+fn path_frames(path: &[glm::Vec3]) -> Vec<(glm::Vec3, glm::Vec3, glm::Vec3)> {
+    let last = path.len() - 1;
+    let tangents: Vec<glm::Vec3> = (0..path.len())
+        .map(|i| {
+            let d = path[(i + 1).min(last)] - path[i.saturating_sub(1)];
+            if glm::length(&d) > 0.0 {
+                glm::normalize(&d)
+            } else {
+                glm::vec3(0.0, 0.0, 1.0)
+            }
+        })
+        .collect();
+
+    let up = if tangents[0].y.abs() < 0.99 {
+        glm::vec3(0.0, 1.0, 0.0)
+    } else {
+        glm::vec3(1.0, 0.0, 0.0)
+    };
+    let mut normal = glm::normalize(&glm::cross(&up, &tangents[0]));
+    tangents
+        .iter()
+        .map(|tangent| {
+            // Carry the normal over from the previous point, removing the part along
+            // the new tangent
+            let projected = normal - tangent * glm::dot(&normal, tangent);
+            if glm::length(&projected) > 0.0 {
+                normal = glm::normalize(&projected);
+            }
+            (*tangent, normal, glm::cross(tangent, &normal))
+        })
+        .collect()
+}
+
+/// Utility function for extruding a 2D shape along a path. The shape's x and y follow
+/// the normal and binormal of the path, so counter clockwise shapes face outwards.
+/// Closed shapes become tubes, open ones ribbons. Fails if the shape needs caps but
+/// can't be triangulated, like when it intersects itself
+#[allow(dead_code)]
+pub fn generate_extrusion(
+    shape: &[glm::Vec2],
+    closed: bool,
+    path: &[glm::Vec3],
+    options: &ExtrudeOptions,
+) -> Result<Mesh, String> {
+    if shape.len() < 2 || path.len() < 2 {
+        return Ok(Mesh::new(Vec::new(), Vec::new()));
+    }
+    let outline = trace_outline(shape, closed);
+    let frames = path_frames(path);
+    let columns = outline.len();
+
+    // How far along the path each point is, from 0 to 1
+    let mut path_distance = vec![0.0f32; path.len()];
+    for i in 1..path.len() {
+        path_distance[i] = path_distance[i - 1] + glm::distance(&path[i - 1], &path[i]);
+    }
+    let path_length = path_distance[path.len() - 1].max(f32::EPSILON);
+
+    // Maps a point of the shape onto the plane at the given point along the path
+    let place = |step: usize, point: &glm::Vec2| {
+        let (_, normal, binormal) = frames[step];
+        let (sin, cos) = (options.twist * step as f32).sin_cos();
+        let scale = options.scale.powi(step as i32);
+        let turned = glm::vec2(point.x * cos - point.y * sin, point.x * sin + point.y * cos);
+        path[step] + (normal * turned.x + binormal * turned.y) * scale
+    };
+
+    let mut vertices = Vec::new();
+    let mut normals = Vec::new();
+    let mut texcoords = Vec::new();
+    let mut triangles = Vec::new();
+    for (step, distance) in path_distance.iter().enumerate() {
+        for point in &outline {
+            let position = place(step, &point.position);
+            // The surface normal is perpendicular both to the shape and to the path,
+            // taking twisting and scaling into account
+            let across = place(step, &(point.position + point.tangent)) - position;
+            let along = place((step + 1).min(path.len() - 1), &point.position)
+                - place(step.saturating_sub(1), &point.position);
+            let normal = glm::cross(&across, &along);
+            let normal = if glm::length(&normal) > 0.0 {
+                glm::normalize(&normal)
+            } else {
+                normal
+            };
+
+            vertices.extend(position.iter());
+            normals.extend(normal.iter());
+            texcoords.extend([point.distance, distance / path_length]);
+        }
+        if step > 0 {
+            let (below, above) = (((step - 1) * columns) as u32, (step * columns) as u32);
+            for column in 0..columns as u32 - 1 {
+                let (a, b) = (below + column, below + column + 1);
+                let (c, d) = (above + column, above + column + 1);
+                triangles.push([a, b, c]);
+                triangles.push([b, d, c]);
+            }
+        }
+    }
+
+    if closed && options.caps {
+        // Texture coordinates for the caps come from the shape itself
+        let lowest = shape.iter().fold(shape[0], |acc, p| glm::min2(&acc, p));
+        let highest = shape.iter().fold(shape[0], |acc, p| glm::max2(&acc, p));
+        let extent = glm::max2(&(highest - lowest), &glm::vec2(f32::EPSILON, f32::EPSILON));
+        let (_, cap) = generate_polygon(shape, &[])
+            .map_err(|e| format!("Failed to cap the extrusion: {}", e))?;
+
+        for &(step, forward) in &[(0, false), (path.len() - 1, true)] {
+            let (tangent, _, _) = frames[step];
+            let normal = if forward { tangent } else { -tangent };
+            let first = (vertices.len() / 3) as u32;
+            for point in shape {
+                let uv = (point - lowest).component_div(&extent);
+                vertices.extend(place(step, point).iter());
+                normals.extend(normal.iter());
+                texcoords.extend([uv.x, uv.y]);
+            }
+            // The triangulation faces ahead, so turn it around for the start
+            for corners in cap.chunks_exact(3) {
+                let (a, b, c) = (first + corners[0], first + corners[1], first + corners[2]);
+                triangles.push(if forward { [a, b, c] } else { [a, c, b] });
+            }
+        }
+    }
+
+    Ok(build_mesh(vertices, normals, texcoords, triangles))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks that there are as many triangles as expected, and that all are counter
+    /// clockwise
+    fn check_triangulation(vertices: &[f32], indices: &[u32], triangles: usize) {
+        assert_eq!(indices.len(), triangles * 3);
+        let point = |i: u32| glm::vec2(vertices[i as usize * 3], vertices[i as usize * 3 + 1]);
+        for corners in indices.chunks_exact(3) {
+            let (a, b, c) = (point(corners[0]), point(corners[1]), point(corners[2]));
+            assert!(cross(&a, &b, &c) > 0.0, "{:?} is clockwise", corners);
+        }
+    }
+
+    /// Checks the amount of vertices and triangles, and that the mesh is fit for drawing
+    fn check_mesh(mesh: &Mesh, vertices: usize, triangles: usize) {
+        assert_eq!(mesh.vertex_count(), vertices);
+        assert_eq!(mesh.indices.len(), triangles * 3);
+        assert_eq!(mesh.validate(), Ok(()));
+    }
+
+    #[test]
+    fn primitives() {
+        check_mesh(&generate_cube(2.0), 24, 12);
+        // The triangles touching the poles have no area, so they are left out
+        check_mesh(&generate_uv_sphere(1.0, 8, 6), 7 * 9, 2 * 6 * 8 - 2 * 8);
+        check_mesh(&generate_cylinder(1.0, 2.0, 8), 6 * 9, 4 * 8);
+        check_mesh(&generate_cone(1.0, 2.0, 8), 4 * 9, 2 * 8);
+        check_mesh(&generate_torus(2.0, 0.5, 8, 6), 7 * 9, 2 * 6 * 8);
+        check_mesh(&generate_capsule(1.0, 4.0, 8, 3), 8 * 9, 2 * 7 * 8 - 2 * 8);
+        check_mesh(&generate_plane(4.0, 2.0, 4, 2), 5 * 3, 2 * 4 * 2);
+
+        // Copies of the vertices along the seam of the texture come on top
+        let icosphere = generate_icosphere(1.0, 2);
+        assert!(icosphere.vertex_count() > 10 * 16 + 2);
+        check_mesh(&icosphere, icosphere.vertex_count(), 20 * 16);
+    }
+
+    #[test]
+    fn lathe() {
+        let segments = 8;
+        let straight = [
+            glm::vec2(0.5, 0.0),
+            glm::vec2(1.0, 1.0),
+            glm::vec2(1.5, 2.0),
+        ];
+        check_mesh(
+            &generate_lathe(&straight, false, segments, FULL_TURN),
+            3 * 9,
+            2 * 2 * 8,
+        );
+
+        // The sharp corner gets a ring for each side, and the top closes at the axis
+        let corner = [
+            glm::vec2(1.0, 0.0),
+            glm::vec2(1.0, 1.0),
+            glm::vec2(0.0, 1.0),
+        ];
+        check_mesh(
+            &generate_lathe(&corner, false, segments, FULL_TURN),
+            4 * 9,
+            2 * 8 + 8,
+        );
+    }
+
+    #[test]
+    fn extrusion() {
+        let square = [
+            glm::vec2(-1.0, -1.0),
+            glm::vec2(1.0, -1.0),
+            glm::vec2(1.0, 1.0),
+            glm::vec2(-1.0, 1.0),
+        ];
+        let path = [
+            glm::vec3(0.0, 0.0, 0.0),
+            glm::vec3(0.0, 0.0, 1.0),
+            glm::vec3(0.0, 0.0, 2.0),
+        ];
+        let options = ExtrudeOptions::default();
+
+        // Every corner is sharp, so each side gets vertices of its own, and each cap is
+        // a copy of the shape
+        let tube = generate_extrusion(&square, true, &path, &options).unwrap();
+        check_mesh(&tube, 3 * 8 + 2 * 4, 2 * 4 * 2 + 2 * 2);
+        let ribbon = generate_extrusion(&square, false, &path, &options).unwrap();
+        check_mesh(&ribbon, 3 * 6, 2 * 3 * 2);
+
+        let bowtie = [
+            glm::vec2(0.0, 0.0),
+            glm::vec2(2.0, 2.0),
+            glm::vec2(2.0, 0.0),
+            glm::vec2(0.0, 2.0),
+        ];
+        assert!(generate_extrusion(&bowtie, true, &path, &options).is_err());
+        let uncapped = ExtrudeOptions {
+            caps: false,
+            ..options
+        };
+        assert!(generate_extrusion(&bowtie, true, &path, &uncapped).is_ok());
+    }
+
+    #[test]
+    fn concave_polygon() {
+        let mut outline = vec![
+            glm::vec2(0.0, 0.0),
+            glm::vec2(2.0, 0.0),
+            glm::vec2(2.0, 1.0),
+            glm::vec2(1.0, 1.0),
+            glm::vec2(1.0, 2.0),
+            glm::vec2(0.0, 2.0),
+        ];
+        let (vertices, indices) = generate_polygon(&outline, &[]).unwrap();
+        check_triangulation(&vertices, &indices, outline.len() - 2);
+
+        // Comes out counter clockwise either way
+        outline.reverse();
+        let (vertices, indices) = generate_polygon(&outline, &[]).unwrap();
+        check_triangulation(&vertices, &indices, outline.len() - 2);
+    }
+
+    #[test]
+    fn polygon_with_hole() {
+        let square = |size: f32| {
+            vec![
+                glm::vec2(-size, -size),
+                glm::vec2(size, -size),
+                glm::vec2(size, size),
+                glm::vec2(-size, size),
+            ]
+        };
+        let (vertices, indices) = generate_polygon(&square(2.0), &[square(1.0)]).unwrap();
+        // Each hole adds two triangles, for the two sides of its bridge
+        check_triangulation(&vertices, &indices, 4 + 4 - 2 + 2);
+    }
+
+    #[test]
+    fn self_intersecting_polygon() {
+        let bowtie = [
+            glm::vec2(0.0, 0.0),
+            glm::vec2(2.0, 2.0),
+            glm::vec2(2.0, 0.0),
+            glm::vec2(0.0, 2.0),
+        ];
+        assert!(generate_polygon(&bowtie, &[]).is_err());
+    }
+}
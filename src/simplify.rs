@@ -0,0 +1,396 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use crate::mesh::Mesh;
+use crate::optimize;
+
+/// How much more than regular faces the planes along borders and seams weigh, to keep
+/// the outline of the mesh in place
+const BORDER_WEIGHT: f64 = 100.0;
+/// Smallest allowed cosine between a triangle's normal after a collapse and its normal
+/// before the collapse, as well as in the original mesh
+const MIN_NORMAL_COSINE: f32 = 0.2;
+
+/// Symmetric 4x4 matrix measuring the squared distance to a set of planes, followed by
+/// the total weight of the planes
+#[derive(Clone, Copy, Default)]
+struct Quadric([f64; 11]);
+
+impl Quadric {
+    fn from_plane(normal: &glm::Vec3, point: &glm::Vec3, weight: f64) -> Self {
+        let (a, b, c) = (normal.x as f64, normal.y as f64, normal.z as f64);
+        let d = -(a * point.x as f64 + b * point.y as f64 + c * point.z as f64);
+        Quadric([
+            a * a * weight,
+            a * b * weight,
+            a * c * weight,
+            a * d * weight,
+            b * b * weight,
+            b * c * weight,
+            b * d * weight,
+            c * c * weight,
+            c * d * weight,
+            d * d * weight,
+            weight,
+        ])
+    }
+
+    fn add(&self, other: &Quadric) -> Quadric {
+        let mut sum = *self;
+        for (value, other) in sum.0.iter_mut().zip(other.0.iter()) {
+            *value += other;
+        }
+        sum
+    }
+
+    fn error(&self, p: &glm::Vec3) -> f64 {
+        let q = &self.0;
+        let (x, y, z) = (p.x as f64, p.y as f64, p.z as f64);
+        let error = q[0] * x * x
+            + 2.0 * q[1] * x * y
+            + 2.0 * q[2] * x * z
+            + 2.0 * q[3] * x
+            + q[4] * y * y
+            + 2.0 * q[5] * y * z
+            + 2.0 * q[6] * y
+            + q[7] * z * z
+            + 2.0 * q[8] * z
+            + q[9];
+        // Average over the planes, so the error is a squared distance
+        if q[10] > 0.0 {
+            error.max(0.0) / q[10]
+        } else {
+            0.0
+        }
+    }
+}
+
+/// A possible collapse of `from` into `to`, ordered so the cheapest comes out of the heap
+/// first. The versions tell whether the collapse is outdated
+struct Collapse {
+    error: f64,
+    from: usize,
+    to: usize,
+    from_version: u32,
+    to_version: u32,
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.error == other.error
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Reversed, as BinaryHeap is a max heap
+        other
+            .error
+            .partial_cmp(&self.error)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+/// Simplifies the mesh with quadric error metrics (Garland and Heckbert), collapsing
+/// edges until there are at most `target_triangles` triangles left, or the next collapse
+/// is estimated to move the surface more than `max_error` away from where it was. The
+/// estimate is an average, so the largest deviation can be a few times that. Vertices along
+/// borders and attribute seams are weighted so the outline and texture layout survive
+pub fn simplify(mesh: &Mesh, target_triangles: usize, max_error: f32) -> Mesh {
+    // Work on positions rather than vertices, so seams where vertices are split for
+    // their normals or texture coordinates don't fall apart
+    let mut position_ids: HashMap<[u32; 3], usize> = HashMap::new();
+    let mut positions: Vec<glm::Vec3> = Vec::new();
+    let vertex_position: Vec<usize> = (0..mesh.vertex_count())
+        .map(|vertex| {
+            let p = mesh.position(vertex);
+            let key = [
+                (p.x + 0.0).to_bits(),
+                (p.y + 0.0).to_bits(),
+                (p.z + 0.0).to_bits(),
+            ];
+            *position_ids.entry(key).or_insert_with(|| {
+                positions.push(p);
+                positions.len() - 1
+            })
+        })
+        .collect();
+
+    let mut triangles: Vec<[u32; 3]> = mesh
+        .indices
+        .chunks_exact(3)
+        .map(|corners| [corners[0], corners[1], corners[2]])
+        .collect();
+    let mut alive = vec![true; triangles.len()];
+    let mut alive_count = triangles.len();
+    let corner_position = |triangle: &[u32; 3], k: usize| vertex_position[triangle[k] as usize];
+
+    // Checking each collapse on its own lets triangles turn a little at a time until
+    // they face the other way, so they are also checked against their original normal
+    let original_normals: Vec<glm::Vec3> = triangles
+        .iter()
+        .map(|triangle| {
+            let corner = |k: usize| positions[corner_position(triangle, k)];
+            let normal = glm::cross(&(corner(1) - corner(0)), &(corner(2) - corner(0)));
+            let length = glm::length(&normal);
+            if length > 0.0 {
+                normal / length
+            } else {
+                glm::zero()
+            }
+        })
+        .collect();
+
+    let mut quadrics = vec![Quadric::default(); positions.len()];
+    let mut position_triangles: Vec<Vec<usize>> = vec![Vec::new(); positions.len()];
+    // Every edge between positions, and how many triangles use each edge between vertices
+    let mut position_edges: HashSet<(usize, usize)> = HashSet::new();
+    let mut vertex_edges: HashMap<(u32, u32), u32> = HashMap::new();
+
+    for (t, triangle) in triangles.iter().enumerate() {
+        let p: Vec<usize> = (0..3).map(|k| corner_position(triangle, k)).collect();
+        let normal = glm::cross(
+            &(positions[p[1]] - positions[p[0]]),
+            &(positions[p[2]] - positions[p[0]]),
+        );
+        let area = glm::length(&normal);
+        if area > 0.0 {
+            let plane = Quadric::from_plane(&(normal / area), &positions[p[0]], area as f64);
+            for &position in &p {
+                quadrics[position] = quadrics[position].add(&plane);
+            }
+        }
+
+        for k in 0..3 {
+            position_triangles[p[k]].push(t);
+            let (a, b) = (p[k], p[(k + 1) % 3]);
+            position_edges.insert((a.min(b), a.max(b)));
+            let (a, b) = (triangle[k], triangle[(k + 1) % 3]);
+            *vertex_edges.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+        }
+    }
+
+    // Edges only used once among the vertices are either borders of the mesh or seams,
+    // add planes perpendicular to them to keep them where they are
+    for triangle in &triangles {
+        let p: Vec<usize> = (0..3).map(|k| corner_position(triangle, k)).collect();
+        let normal = glm::cross(
+            &(positions[p[1]] - positions[p[0]]),
+            &(positions[p[2]] - positions[p[0]]),
+        );
+        for k in 0..3 {
+            let (a, b) = (triangle[k], triangle[(k + 1) % 3]);
+            if vertex_edges[&(a.min(b), a.max(b))] != 1 {
+                continue;
+            }
+            let (pa, pb) = (positions[p[k]], positions[p[(k + 1) % 3]]);
+            let edge = pb - pa;
+            let perpendicular = glm::cross(&edge, &normal);
+            let length = glm::length(&perpendicular);
+            if length > 0.0 {
+                let plane = Quadric::from_plane(
+                    &(perpendicular / length),
+                    &pa,
+                    BORDER_WEIGHT * glm::length2(&edge) as f64,
+                );
+                quadrics[p[k]] = quadrics[p[k]].add(&plane);
+                quadrics[p[(k + 1) % 3]] = quadrics[p[(k + 1) % 3]].add(&plane);
+            }
+        }
+    }
+
+    let mut versions = vec![0u32; positions.len()];
+    let mut removed = vec![false; positions.len()];
+    let mut heap: BinaryHeap<Collapse> = BinaryHeap::new();
+    let cheapest = |quadrics: &Vec<Quadric>, versions: &Vec<u32>, a: usize, b: usize| {
+        let quadric = quadrics[a].add(&quadrics[b]);
+        let (error_ab, error_ba) = (quadric.error(&positions[b]), quadric.error(&positions[a]));
+        let (from, to, error) = if error_ab <= error_ba {
+            (a, b, error_ab)
+        } else {
+            (b, a, error_ba)
+        };
+        Collapse {
+            error,
+            from,
+            to,
+            from_version: versions[from],
+            to_version: versions[to],
+        }
+    };
+    for &(a, b) in &position_edges {
+        heap.push(cheapest(&quadrics, &versions, a, b));
+    }
+
+    let max_error = (max_error as f64) * (max_error as f64);
+    while alive_count > target_triangles {
+        let collapse = match heap.pop() {
+            Some(collapse) => collapse,
+            None => break,
+        };
+        if collapse.error > max_error {
+            break;
+        }
+        let (from, to) = (collapse.from, collapse.to);
+        if removed[from]
+            || removed[to]
+            || versions[from] != collapse.from_version
+            || versions[to] != collapse.to_version
+        {
+            continue;
+        }
+
+        // Make sure no triangle flips or collapses into a sliver by moving `from`
+        let flips = position_triangles[from].iter().any(|&t| {
+            if !alive[t] {
+                return false;
+            }
+            let p: Vec<usize> = (0..3).map(|k| corner_position(&triangles[t], k)).collect();
+            if p.contains(&to) {
+                return false;
+            }
+            let corner = |k: usize| positions[p[k]];
+            let moved = |k: usize| {
+                if p[k] == from {
+                    positions[to]
+                } else {
+                    corner(k)
+                }
+            };
+            let before = glm::cross(&(corner(1) - corner(0)), &(corner(2) - corner(0)));
+            let after = glm::cross(&(moved(1) - moved(0)), &(moved(2) - moved(0)));
+            let (before_length, after_length) = (glm::length(&before), glm::length(&after));
+            after_length <= 0.0
+                || before_length <= 0.0
+                || glm::dot(&before, &after) < MIN_NORMAL_COSINE * before_length * after_length
+                || glm::dot(&original_normals[t], &after) < MIN_NORMAL_COSINE * after_length
+        });
+        if flips {
+            continue;
+        }
+
+        // Figure out which vertex at `to` each vertex at `from` should turn into. Across
+        // the collapsed edge the triangles tell us, otherwise any vertex there will do
+        let mut vertex_map: HashMap<u32, u32> = HashMap::new();
+        let mut fallback: Option<u32> = None;
+        for &t in &position_triangles[to] {
+            if !alive[t] {
+                continue;
+            }
+            let triangle = triangles[t];
+            let at = |target: usize| {
+                (0..3)
+                    .find(|&k| corner_position(&triangle, k) == target)
+                    .map(|k| triangle[k])
+            };
+            if let Some(to_vertex) = at(to) {
+                fallback.get_or_insert(to_vertex);
+                if let Some(from_vertex) = at(from) {
+                    vertex_map.entry(from_vertex).or_insert(to_vertex);
+                }
+            }
+        }
+        let fallback = match fallback {
+            Some(vertex) => vertex,
+            None => continue,
+        };
+
+        let from_triangles = std::mem::take(&mut position_triangles[from]);
+        for &t in &from_triangles {
+            if !alive[t] {
+                continue;
+            }
+            let p: Vec<usize> = (0..3).map(|k| corner_position(&triangles[t], k)).collect();
+            if p.contains(&to) {
+                alive[t] = false;
+                alive_count -= 1;
+                continue;
+            }
+            for k in 0..3 {
+                if p[k] == from {
+                    let vertex = triangles[t][k];
+                    triangles[t][k] = *vertex_map.get(&vertex).unwrap_or(&fallback);
+                }
+            }
+            position_triangles[to].push(t);
+        }
+
+        removed[from] = true;
+        quadrics[to] = quadrics[to].add(&quadrics[from]);
+        versions[to] += 1;
+
+        // Queue up new collapses for every edge around the merged position
+        let mut neighbours: Vec<usize> = position_triangles[to]
+            .iter()
+            .filter(|&&t| alive[t])
+            .flat_map(|&t| (0..3).map(move |k| (t, k)))
+            .map(|(t, k)| corner_position(&triangles[t], k))
+            .filter(|&p| p != to)
+            .collect();
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        for neighbour in neighbours {
+            heap.push(cheapest(&quadrics, &versions, to, neighbour));
+        }
+    }
+
+    let mut result = mesh.clone();
+    result.indices = triangles
+        .iter()
+        .zip(alive.iter())
+        .filter(|(_, &alive)| alive)
+        .flat_map(|(triangle, _)| triangle.iter().cloned())
+        .collect();
+    result.index_count = result.indices.len() as i32;
+    // Gets rid of all the vertices that were collapsed away
+    optimize::optimize_vertex_fetch(&mut result);
+    result
+}
+
+/// Makes increasingly simple versions of the mesh, each with `ratio` times the
+/// triangles of the one before it
+pub fn lod_chain(mesh: &Mesh, levels: usize, ratio: f32) -> Vec<Mesh> {
+    let mut chain: Vec<Mesh> = Vec::with_capacity(levels);
+    let mut target = mesh.indices.len() / 3;
+    for _ in 0..levels {
+        target = (target as f32 * ratio) as usize;
+        let previous = chain.last().unwrap_or(mesh);
+        chain.push(simplify(previous, target, f32::MAX));
+    }
+    chain
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heightmap::{self, NoiseSettings};
+
+    #[test]
+    fn reaches_target_without_flipping() {
+        let mesh = heightmap::from_noise(65, 600.0, 60.0, &NoiseSettings::default());
+        let target = mesh.indices.len() / 3 / 8;
+        let simplified = simplify(&mesh, target, f32::MAX);
+
+        let triangles = simplified.indices.len() / 3;
+        // Collapses remove one or two triangles at a time, so it may go slightly below
+        assert!(
+            triangles <= target && triangles + 2 >= target,
+            "{} triangles",
+            triangles
+        );
+        // The terrain faces up everywhere, so no triangle should end up facing down
+        for corners in simplified.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| simplified.position(corners[i] as usize));
+            assert!(glm::cross(&(b - a), &(c - a)).y >= 0.0);
+        }
+        assert!(simplified.validate().is_ok());
+    }
+}
@@ -0,0 +1,306 @@
+/// Steering is stepped at least this often, so it stays stable at low frame rates
+const MAX_STEERING_STEP: f32 = 1.0 / 60.0;
+/// Pushing two agents apart can push them into others, so separating goes over every
+/// pair up to this many times, until nobody is too close anymore
+const MAX_SEPARATION_PASSES: usize = 32;
+
+/// Names of the weights that can be tuned while running, in the order `weight_mut`
+/// takes them
+pub const WEIGHT_NAMES: [&str; 5] = ["separation", "alignment", "cohesion", "seek", "avoidance"];
+
+/// Tunable weights and limits of the steering behaviours
+#[derive(Clone, Copy, Debug)]
+pub struct SteeringSettings {
+    pub separation: f32, // Steers away from neighbours that are too close
+    pub alignment: f32,  // Steers towards the average heading of neighbours
+    pub cohesion: f32,   // Steers towards the center of neighbours
+    pub seek: f32,       // Steers towards the agent's own target
+    pub avoidance: f32,  // Steers up and away from the terrain
+    pub separation_radius: f32,
+    pub neighbour_radius: f32,
+    pub arrive_radius: f32, // Agents slow down within this distance of their target
+    pub radius: f32,        // Agents are never allowed closer than twice this
+    pub clearance: f32,     // Height to keep above the terrain
+    pub look_ahead: f32,    // Seconds ahead to look for terrain in the way
+    pub max_speed: f32,
+    pub max_force: f32,
+}
+
+impl Default for SteeringSettings {
+    fn default() -> Self {
+        SteeringSettings {
+            separation: 2.0,
+            alignment: 0.5,
+            cohesion: 0.3,
+            seek: 1.0,
+            avoidance: 4.0,
+            separation_radius: 30.0,
+            neighbour_radius: 60.0,
+            arrive_radius: 20.0,
+            radius: 7.0,
+            clearance: 10.0,
+            look_ahead: 1.5,
+            max_speed: 45.0,
+            max_force: 60.0,
+        }
+    }
+}
+
+impl SteeringSettings {
+    /// One of the weights, by its index in WEIGHT_NAMES
+    pub fn weight_mut(&mut self, index: usize) -> &mut f32 {
+        match index {
+            0 => &mut self.separation,
+            1 => &mut self.alignment,
+            2 => &mut self.cohesion,
+            3 => &mut self.seek,
+            _ => &mut self.avoidance,
+        }
+    }
+}
+
+/// Something moving around with the flock
+#[derive(Clone, Copy, Debug)]
+pub struct Agent {
+    pub position: glm::Vec3,
+    pub velocity: glm::Vec3,
+    pub acceleration: glm::Vec3, // Average over the last update, for banking into turns
+}
+
+/// Agents that steer around each other and the terrain on their way to their targets
+pub struct Flock {
+    pub agents: Vec<Agent>,
+}
+
+/// Limits the length of the vector
+fn truncate(v: glm::Vec3, max: f32) -> glm::Vec3 {
+    let length = glm::length(&v);
+    if length > max {
+        v * (max / length)
+    } else {
+        v
+    }
+}
+
+impl Flock {
+    /// Starts the agents at rest at the given positions
+    pub fn new(positions: &[glm::Vec3]) -> Self {
+        Flock {
+            agents: positions
+                .iter()
+                .map(|&position| Agent {
+                    position,
+                    velocity: glm::zero(),
+                    acceleration: glm::zero(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Sum of the weighted steering behaviours for the agent, given its target and the
+    /// highest ground below it and where it is headed
+    fn steering_force(
+        &self,
+        index: usize,
+        target: &glm::Vec3,
+        ground: f32,
+        settings: &SteeringSettings,
+    ) -> glm::Vec3 {
+        let agent = &self.agents[index];
+        let mut separation = glm::Vec3::zeros();
+        let mut heading = glm::Vec3::zeros();
+        let mut center = glm::Vec3::zeros();
+        let mut neighbours = 0;
+        for (other_index, other) in self.agents.iter().enumerate() {
+            if other_index == index {
+                continue;
+            }
+            let away = agent.position - other.position;
+            let distance = glm::length(&away);
+            if distance < settings.separation_radius && distance > 0.0 {
+                // Nothing at the edge of the radius, pushing harder the closer they are
+                separation += away / distance * (settings.separation_radius / distance - 1.0);
+            }
+            if distance < settings.neighbour_radius {
+                heading += other.velocity;
+                center += other.position;
+                neighbours += 1;
+            }
+        }
+
+        // Reynolds' steering: behaviours ask for a velocity, and the force is what it
+        // takes to get there from the current one
+        let desire = |velocity: glm::Vec3| {
+            if glm::length(&velocity) > 0.0 {
+                velocity - agent.velocity
+            } else {
+                glm::zero()
+            }
+        };
+        let arrive = |target: &glm::Vec3| {
+            let offset = target - agent.position;
+            let distance = glm::length(&offset);
+            if distance > 0.0 {
+                let speed = settings.max_speed * (distance / settings.arrive_radius).min(1.0);
+                offset * (speed / distance) - agent.velocity
+            } else {
+                -agent.velocity
+            }
+        };
+
+        // Separation pushes rather than asking for a velocity, so it doesn't hold back
+        // agents that are only a little too close
+        let separation = truncate(separation * settings.max_speed, settings.max_speed);
+        let mut force = separation * settings.separation + arrive(target) * settings.seek;
+        if neighbours > 0 {
+            let count = neighbours as f32;
+            force += desire(heading / count) * settings.alignment;
+            force += arrive(&(center / count)) * settings.cohesion;
+        }
+
+        // Climb harder the deeper into the clearance the agent is, and stop sinking
+        let depth = ground + settings.clearance - agent.position.y;
+        if depth > 0.0 {
+            let climb = depth / settings.clearance * settings.max_speed;
+            force.y += (climb - agent.velocity.y.min(0.0)) * settings.avoidance;
+        }
+
+        truncate(force, settings.max_force)
+    }
+
+    /// Steers every agent towards its target, with one target per agent. `ground_height`
+    /// gives the height of the terrain at a point along the ground, if there is any
+    pub fn update<H>(
+        &mut self,
+        targets: &[glm::Vec3],
+        ground_height: H,
+        settings: &SteeringSettings,
+        delta_time: f32,
+    ) where
+        H: Fn(f32, f32) -> Option<f32>,
+    {
+        // Ground barely moves under an agent during a frame, so steering looks it up once
+        let grounds: Vec<f32> = self
+            .agents
+            .iter()
+            .map(|agent| {
+                let ahead = agent.position + agent.velocity * settings.look_ahead;
+                let below = ground_height(agent.position.x, agent.position.z);
+                let further = ground_height(ahead.x, ahead.z);
+                below
+                    .unwrap_or(f32::NEG_INFINITY)
+                    .max(further.unwrap_or(f32::NEG_INFINITY))
+            })
+            .collect();
+        let velocities: Vec<glm::Vec3> = self.agents.iter().map(|a| a.velocity).collect();
+        // Lowest an agent may go, which has to be looked up again wherever separating
+        // pushes it to
+        let floor =
+            |x: f32, z: f32| ground_height(x, z).unwrap_or(f32::NEG_INFINITY) + settings.radius;
+
+        let steps = (delta_time / MAX_STEERING_STEP).ceil().max(1.0);
+        let step = delta_time / steps;
+        for _ in 0..steps as u32 {
+            let forces: Vec<glm::Vec3> = (0..self.agents.len())
+                .map(|i| self.steering_force(i, &targets[i], grounds[i], settings))
+                .collect();
+            for (agent, force) in self.agents.iter_mut().zip(forces) {
+                agent.velocity = truncate(agent.velocity + force * step, settings.max_speed);
+                agent.position += agent.velocity * step;
+            }
+            // Whatever the forces say, never go through the ground
+            self.separate(settings.radius * 2.0, &floor);
+        }
+
+        for (agent, velocity) in self.agents.iter_mut().zip(velocities) {
+            // Stop sinking once on the ground
+            if agent.position.y <= floor(agent.position.x, agent.position.z) {
+                agent.velocity.y = agent.velocity.y.max(0.0);
+            }
+            if delta_time > 0.0 {
+                agent.acceleration = (agent.velocity - velocity) / delta_time;
+            }
+        }
+    }
+
+    /// Pushes apart agents closer than the given distance, half each way, while keeping
+    /// each agent at or above the floor at the given point along the ground
+    fn separate<F>(&mut self, distance: f32, floor: &F)
+    where
+        F: Fn(f32, f32) -> f32,
+    {
+        for _ in 0..MAX_SEPARATION_PASSES {
+            let mut overlapping = false;
+            for i in 0..self.agents.len() {
+                for j in i + 1..self.agents.len() {
+                    let offset = self.agents[i].position - self.agents[j].position;
+                    let length = glm::length(&offset);
+                    if length >= distance {
+                        continue;
+                    }
+                    overlapping = true;
+                    // Agents right on top of each other have no direction to go, so pick one
+                    let direction = if length > 0.0 {
+                        offset / length
+                    } else {
+                        glm::vec3(1.0, 0.0, 0.0)
+                    };
+                    let push = direction * ((distance - length) / 2.0);
+                    self.agents[i].position += push;
+                    self.agents[j].position -= push;
+                }
+            }
+            for agent in self.agents.iter_mut() {
+                let p = agent.position;
+                agent.position.y = p.y.max(floor(p.x, p.z));
+            }
+            if !overlapping {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_apart_and_above_ground() {
+        let settings = SteeringSettings::default();
+        // Rolling hills, so the ground changes under the agents as they go
+        let ground = |x: f32, z: f32| Some((x * 0.05).sin() * 20.0 + (z * 0.03).cos() * 10.0);
+
+        // Start everyone packed into a space much smaller than they need
+        let positions: Vec<glm::Vec3> = (0..8)
+            .map(|i| glm::vec3((i % 3) as f32, 25.0 + (i / 3) as f32, 0.5 * i as f32))
+            .collect();
+        let mut flock = Flock::new(&positions);
+        let targets = vec![glm::vec3(200.0, 30.0, -100.0); positions.len()];
+
+        for frame in 0..300 {
+            flock.update(&targets, ground, &settings, 1.0 / 30.0);
+            for (i, a) in flock.agents.iter().enumerate() {
+                let below = ground(a.position.x, a.position.z).unwrap();
+                assert!(
+                    a.position.y >= below + settings.radius - 1e-3,
+                    "agent {} is {} above ground in frame {}",
+                    i,
+                    a.position.y - below,
+                    frame
+                );
+                for (j, b) in flock.agents.iter().enumerate().skip(i + 1) {
+                    let distance = glm::distance(&a.position, &b.position);
+                    assert!(
+                        distance >= settings.radius * 2.0 - 1e-3,
+                        "agents {} and {} are {} apart in frame {}",
+                        i,
+                        j,
+                        distance,
+                        frame
+                    );
+                }
+            }
+        }
+    }
+}
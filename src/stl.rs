@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+
+use crate::mesh::Mesh;
+
+/// Size of the header and the triangle count of binary STL files
+const BINARY_HEADER_SIZE: usize = 84;
+/// Size of a single triangle in binary STL files (normal, 3 vertices and attributes)
+const BINARY_TRIANGLE_SIZE: usize = 50;
+
+/// Merges vertices with the exact same position, as STL stores every triangle separately
+struct Welder {
+    lookup: HashMap<[u32; 3], u32>,
+    vertices: Vec<f32>,
+    indices: Vec<u32>,
+}
+
+impl Welder {
+    fn new() -> Self {
+        Welder {
+            lookup: HashMap::new(),
+            vertices: Vec::new(),
+            indices: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, position: [f32; 3]) {
+        // Treat -0.0 and 0.0 as the same position
+        let key = [
+            (position[0] + 0.0).to_bits(),
+            (position[1] + 0.0).to_bits(),
+            (position[2] + 0.0).to_bits(),
+        ];
+        let vertices = &mut self.vertices;
+        let index = *self.lookup.entry(key).or_insert_with(|| {
+            vertices.extend(position.iter());
+            (vertices.len() / 3 - 1) as u32
+        });
+        self.indices.push(index);
+    }
+
+    fn into_mesh(self) -> Mesh {
+        let mut mesh = Mesh::new(self.vertices, self.indices);
+        // STL only has face normals, so let smoothing take care of the hard edges
+        mesh.generate_missing_attributes();
+        mesh
+    }
+}
+
+fn is_binary(data: &[u8]) -> bool {
+    if data.len() < BINARY_HEADER_SIZE {
+        return false;
+    }
+    // Some binary files start with "solid" as well, so rely on the size instead. Some
+    // exporters pad the end of the file, so there may be more data than triangles
+    data.len() >= BINARY_HEADER_SIZE + triangle_count(data) * BINARY_TRIANGLE_SIZE
+}
+
+/// Number of triangles according to the header of a binary STL file
+fn triangle_count(data: &[u8]) -> usize {
+    u32::from_le_bytes([data[80], data[81], data[82], data[83]]) as usize
+}
+
+fn parse_binary(data: &[u8]) -> Mesh {
+    let mut welder = Welder::new();
+    let read_f32 = |bytes: &[u8]| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+
+    let triangles = data[BINARY_HEADER_SIZE..].chunks_exact(BINARY_TRIANGLE_SIZE);
+    for triangle in triangles.take(triangle_count(data)) {
+        // Skip the face normal, which is the first three floats
+        for vertex in triangle[12..48].chunks_exact(12) {
+            welder.add([
+                read_f32(&vertex[0..4]),
+                read_f32(&vertex[4..8]),
+                read_f32(&vertex[8..12]),
+            ]);
+        }
+    }
+
+    welder.into_mesh()
+}
+
+fn parse_ascii(data: &[u8]) -> Result<Mesh, String> {
+    let text = String::from_utf8_lossy(data);
+    if !text.trim_start().starts_with("solid") {
+        return Err("Not an STL file".to_string());
+    }
+
+    let mut welder = Welder::new();
+    for line in text.lines() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if let ["vertex", x, y, z] = words.as_slice() {
+            let parse = |value: &str| {
+                value
+                    .parse::<f32>()
+                    .map_err(|_| format!("Invalid number {}", value))
+            };
+            welder.add([parse(x)?, parse(y)?, parse(z)?]);
+        }
+    }
+
+    if welder.indices.is_empty() {
+        return Err("No facets found".to_string());
+    }
+    if !welder.indices.len().is_multiple_of(3) {
+        return Err("Facet with a vertex count other than 3".to_string());
+    }
+    Ok(welder.into_mesh())
+}
+
+/// Parses an ASCII or binary STL file, welding vertices that share a position
+pub fn parse(data: &[u8]) -> Result<Mesh, String> {
+    if is_binary(data) {
+        Ok(parse_binary(data))
+    } else {
+        parse_ascii(data)
+    }
+}
+
+#[allow(dead_code)]
+pub fn load(path: &str) -> Mesh {
+    println!("Loading STL model...");
+    let before = std::time::Instant::now();
+    let data = std::fs::read(path).unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e));
+    let mesh = parse(&data).unwrap_or_else(|e| panic!("Failed to load STL model {}: {}", path, e));
+    let after = std::time::Instant::now();
+    println!(
+        "Done in {:.3}ms. Loaded {} points and {} triangles.",
+        after.duration_since(before).as_micros() as f32 / 1e3,
+        mesh.vertex_count(),
+        mesh.indices.len() / 3,
+    );
+    mesh
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two triangles making up a unit square, which share two of their corners
+    const SQUARE: [[[f32; 3]; 3]; 2] = [
+        [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0]],
+        [[0.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]],
+    ];
+
+    fn check_square(mesh: &Mesh) {
+        assert_eq!(mesh.vertex_count(), 4);
+        assert_eq!(mesh.indices.len(), 6);
+        for (corner, &index) in mesh.indices.iter().enumerate() {
+            let expected = SQUARE[corner / 3][corner % 3];
+            assert_eq!(mesh.position(index as usize), glm::make_vec3(&expected));
+        }
+    }
+
+    #[test]
+    fn binary() {
+        // Starts with "solid" like some exporters write it, and is padded at the end
+        let mut data = b"solid square".to_vec();
+        data.resize(80, 0);
+        data.extend(&(SQUARE.len() as u32).to_le_bytes());
+        for triangle in &SQUARE {
+            data.extend([0.0f32, 0.0, 1.0].iter().flat_map(|v| v.to_le_bytes()));
+            for corner in triangle {
+                data.extend(corner.iter().flat_map(|v| v.to_le_bytes()));
+            }
+            data.extend(&[0, 0]);
+        }
+        data.extend(&[0; 16]);
+
+        check_square(&parse(&data).unwrap());
+    }
+
+    #[test]
+    fn ascii() {
+        let mut text = "solid square\n".to_string();
+        for triangle in &SQUARE {
+            text += "facet normal 0 0 1\nouter loop\n";
+            for corner in triangle {
+                text += &format!("vertex {} {} {}\n", corner[0], corner[1], corner[2]);
+            }
+            text += "endloop\nendfacet\n";
+        }
+        text += "endsolid square\n";
+
+        check_square(&parse(text.as_bytes()).unwrap());
+        assert!(parse(b"solid empty\nendsolid empty\n").is_err());
+    }
+}
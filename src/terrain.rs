@@ -0,0 +1,520 @@
+use std::ops::Range;
+
+use crate::camera;
+use crate::mesh::Mesh;
+use crate::optimize::{self, OptimizationReport};
+use crate::scene_graph::LodLevel;
+
+/// Quadtree nodes are split no further than this, however many triangles they have
+const MAX_DEPTH: u32 = 8;
+/// Roughly how many triangles end up in each cell of a chunk's height grid
+const TRIANGLES_PER_CELL: usize = 8;
+
+/// The triangles of a chunk sorted into a grid of cells along the ground (x and z), so
+/// finding the height at a point only has to look at the triangles of one cell
+struct HeightGrid {
+    min: glm::Vec2,
+    max: glm::Vec2,
+    cell_size: glm::Vec2,
+    columns: usize,
+    rows: usize,
+    cells: Vec<Vec<u32>>, // Triangles overlapping each cell, counted from the first
+}
+
+impl HeightGrid {
+    /// Sorts the triangles made up by the given indices into a grid covering them
+    fn new(mesh: &Mesh, indices: &[u32]) -> HeightGrid {
+        let triangles: Vec<[glm::Vec2; 3]> = indices
+            .chunks_exact(3)
+            .map(|corners| {
+                let corner = |i: usize| {
+                    let p = mesh.position(corners[i] as usize);
+                    glm::vec2(p.x, p.z)
+                };
+                [corner(0), corner(1), corner(2)]
+            })
+            .collect();
+        let (min, max) = triangles.iter().flatten().fold(
+            (glm::vec2(f32::MAX, f32::MAX), glm::vec2(f32::MIN, f32::MIN)),
+            |(min, max), p| (glm::min2(&min, p), glm::max2(&max, p)),
+        );
+
+        let side = ((triangles.len() / TRIANGLES_PER_CELL) as f32)
+            .sqrt()
+            .ceil()
+            .max(1.0);
+        let mut grid = HeightGrid {
+            min,
+            max,
+            cell_size: (max - min) / side,
+            columns: side as usize,
+            rows: side as usize,
+            cells: vec![Vec::new(); (side * side) as usize],
+        };
+        for (t, corners) in triangles.iter().enumerate() {
+            let low = corners.iter().fold(corners[0], |low, p| glm::min2(&low, p));
+            let high = corners
+                .iter()
+                .fold(corners[0], |high, p| glm::max2(&high, p));
+            let (first_column, first_row) = grid.cell(low);
+            let (last_column, last_row) = grid.cell(high);
+            for row in first_row..=last_row {
+                for column in first_column..=last_column {
+                    grid.cells[row * grid.columns + column].push(t as u32);
+                }
+            }
+        }
+        grid
+    }
+
+    /// Column and row of the cell the point is in, or the closest one if it is outside
+    fn cell(&self, point: glm::Vec2) -> (usize, usize) {
+        // Casting saturates, which also takes care of the grid being flat along an axis
+        let column = ((point.x - self.min.x) / self.cell_size.x) as usize;
+        let row = ((point.y - self.min.y) / self.cell_size.y) as usize;
+        (column.min(self.columns - 1), row.min(self.rows - 1))
+    }
+
+    /// Triangles which might be above or below the point, none if it is off the grid
+    fn triangles_at(&self, point: glm::Vec2) -> &[u32] {
+        if point.x < self.min.x
+            || point.x > self.max.x
+            || point.y < self.min.y
+            || point.y > self.max.y
+        {
+            return &[];
+        }
+        let (column, row) = self.cell(point);
+        &self.cells[row * self.columns + column]
+    }
+}
+
+/// A part of the terrain that can be culled and drawn on its own, as a range of the
+/// indices of the terrain mesh
+pub struct Chunk {
+    pub first_index: usize,
+    pub index_count: i32,
+    pub min: glm::Vec3,
+    pub max: glm::Vec3,
+    pub lods: Vec<LodLevel>, // Simpler versions of the chunk, empty until uploaded
+    pub resident: bool,      // Whether every triangle of the chunk should be kept around
+    pub index_buffer_id: u32, // Where the triangles are uploaded to while resident, or 0
+    grid: HeightGrid,
+}
+
+impl Chunk {
+    /// Where the triangles of this chunk are in the indices of the terrain mesh
+    pub fn indices(&self) -> Range<usize> {
+        self.first_index..self.first_index + self.index_count as usize
+    }
+
+    /// Center and radius of a sphere around the bounds of the chunk
+    pub fn bounding_sphere(&self) -> (glm::Vec3, f32) {
+        (
+            (self.min + self.max) / 2.0,
+            glm::distance(&self.min, &self.max) / 2.0,
+        )
+    }
+
+    /// Distance from the given point along the ground (x and z) to the bounds of the
+    /// chunk, 0 if the point is above or below it
+    pub fn ground_distance(&self, point: glm::Vec2) -> f32 {
+        let min = glm::vec2(self.min.x, self.min.z);
+        let max = glm::vec2(self.max.x, self.max.z);
+        glm::distance(&point, &glm::clamp_vec(&point, &min, &max))
+    }
+
+    /// Height of the highest triangle above or below the given point along the ground,
+    /// or None if there is none
+    pub fn height_at(&self, mesh: &Mesh, x: f32, z: f32) -> Option<f32> {
+        let mut height: Option<f32> = None;
+        for &t in self.grid.triangles_at(glm::vec2(x, z)) {
+            let first = self.first_index + t as usize * 3;
+            let corners = &mesh.indices[first..first + 3];
+            let (a, b, c) = (
+                mesh.position(corners[0] as usize),
+                mesh.position(corners[1] as usize),
+                mesh.position(corners[2] as usize),
+            );
+            // Barycentric coordinates of the point in the triangle, seen from above.
+            // Triangles seen edge on have no area to speak of next to their size
+            let determinant = (b.z - c.z) * (a.x - c.x) + (c.x - b.x) * (a.z - c.z);
+            let longest_edge = [a - b, b - c, c - a]
+                .iter()
+                .map(|edge| edge.x * edge.x + edge.z * edge.z)
+                .fold(0.0, f32::max);
+            if determinant.abs() <= f32::EPSILON * longest_edge {
+                continue;
+            }
+            let u = ((b.z - c.z) * (x - c.x) + (c.x - b.x) * (z - c.z)) / determinant;
+            let v = ((c.z - a.z) * (x - c.x) + (a.x - c.x) * (z - c.z)) / determinant;
+            let w = 1.0 - u - v;
+            if u < -1e-5 || v < -1e-5 || w < -1e-5 {
+                continue;
+            }
+            let y = a.y * u + b.y * v + c.y * w;
+            height = Some(height.map_or(y, |h| h.max(y)));
+        }
+        height
+    }
+}
+
+/// Chunks that became resident or were evicted in `ChunkedTerrain::update_residency`
+#[derive(Debug, Default, PartialEq)]
+pub struct ResidencyChanges {
+    pub loaded: Vec<usize>,
+    pub evicted: Vec<usize>,
+}
+
+/// A square area of the terrain, either split into four smaller ones or holding a chunk
+pub struct QuadtreeNode {
+    pub min: glm::Vec3,
+    pub max: glm::Vec3,
+    pub children: Vec<QuadtreeNode>, // Empty for leaves
+    pub chunk: Option<usize>,        // Index into ChunkedTerrain::chunks, for leaves
+}
+
+/// Terrain split into chunks along a quadtree, so it can be culled in pieces instead of
+/// as a whole. The chunks share a single mesh, with the triangles of each chunk next to
+/// each other. Only the chunks near the camera need all their triangles resident, the
+/// rest can make do with their levels of detail
+pub struct ChunkedTerrain {
+    pub mesh: Mesh, // Reorder it with optimize, which keeps the height grids up to date
+    pub chunks: Vec<Chunk>,
+    pub root: QuadtreeNode,
+    pub vao_id: u32, // 0 until the mesh is uploaded
+}
+
+/// Bounds of the given triangles, or None if there are none
+fn triangle_bounds(mesh: &Mesh, triangles: &[usize]) -> Option<(glm::Vec3, glm::Vec3)> {
+    let mut corners = triangles
+        .iter()
+        .flat_map(|&t| mesh.indices[t * 3..t * 3 + 3].iter())
+        .map(|&index| mesh.position(index as usize));
+    let first = corners.next()?;
+    Some(corners.fold((first, first), |(min, max), p| {
+        (glm::min2(&min, &p), glm::max2(&max, &p))
+    }))
+}
+
+/// State shared while building the quadtree
+struct Builder<'a> {
+    mesh: &'a Mesh,
+    centers: Vec<glm::Vec3>,
+    max_triangles: usize,
+    chunks: Vec<Chunk>,
+    indices: Vec<u32>, // Triangles of every chunk so far, one chunk after the other
+}
+
+impl<'a> Builder<'a> {
+    fn split(
+        &mut self,
+        triangles: Vec<usize>,
+        min: glm::Vec3,
+        max: glm::Vec3,
+        depth: u32,
+    ) -> QuadtreeNode {
+        if triangles.len() <= self.max_triangles || depth >= MAX_DEPTH {
+            // The square the node covers might be larger than what is in it, so tighten
+            // the bounds to the triangles themselves
+            let (min, max) = triangle_bounds(self.mesh, &triangles).unwrap_or((min, max));
+            let first_index = self.indices.len();
+            for &t in &triangles {
+                self.indices
+                    .extend_from_slice(&self.mesh.indices[t * 3..t * 3 + 3]);
+            }
+            self.chunks.push(Chunk {
+                first_index,
+                index_count: (self.indices.len() - first_index) as i32,
+                min,
+                max,
+                lods: Vec::new(),
+                resident: false,
+                index_buffer_id: 0,
+                grid: HeightGrid::new(self.mesh, &self.indices[first_index..]),
+            });
+            return QuadtreeNode {
+                min,
+                max,
+                children: Vec::new(),
+                chunk: Some(self.chunks.len() - 1),
+            };
+        }
+
+        let middle = (min + max) / 2.0;
+        let mut quadrants: [Vec<usize>; 4] = Default::default();
+        for t in triangles {
+            let center = self.centers[t];
+            let quadrant = (center.x >= middle.x) as usize + 2 * (center.z >= middle.z) as usize;
+            quadrants[quadrant].push(t);
+        }
+
+        let mut children = Vec::new();
+        for (quadrant, triangles) in quadrants.iter_mut().enumerate() {
+            if triangles.is_empty() {
+                continue;
+            }
+            let (x, z) = (quadrant % 2 == 1, quadrant / 2 == 1);
+            let child_min = glm::vec3(
+                if x { middle.x } else { min.x },
+                min.y,
+                if z { middle.z } else { min.z },
+            );
+            let child_max = glm::vec3(
+                if x { max.x } else { middle.x },
+                max.y,
+                if z { max.z } else { middle.z },
+            );
+            let triangles = std::mem::take(triangles);
+            children.push(self.split(triangles, child_min, child_max, depth + 1));
+        }
+
+        // Children tighten their bounds, so the parent can too
+        let (min, max) = children
+            .iter()
+            .fold((children[0].min, children[0].max), |(min, max), child| {
+                (glm::min2(&min, &child.min), glm::max2(&max, &child.max))
+            });
+        QuadtreeNode {
+            min,
+            max,
+            children,
+            chunk: None,
+        }
+    }
+}
+
+impl ChunkedTerrain {
+    /// Splits the mesh along the ground (x and z) until every chunk has at most
+    /// `max_triangles` triangles. Triangles go to the chunk their center is in
+    pub fn new(mesh: &Mesh, max_triangles: usize) -> ChunkedTerrain {
+        let triangles: Vec<usize> = (0..mesh.indices.len() / 3).collect();
+        let centers: Vec<glm::Vec3> = mesh
+            .indices
+            .chunks_exact(3)
+            .map(|corners| {
+                corners.iter().fold(glm::Vec3::zeros(), |sum, &index| {
+                    sum + mesh.position(index as usize)
+                }) / 3.0
+            })
+            .collect();
+        let (min, max) =
+            triangle_bounds(mesh, &triangles).unwrap_or((glm::Vec3::zeros(), glm::Vec3::zeros()));
+
+        let mut builder = Builder {
+            mesh,
+            centers,
+            max_triangles,
+            chunks: Vec::new(),
+            indices: Vec::with_capacity(mesh.indices.len()),
+        };
+        let root = builder.split(triangles, min, max, 0);
+
+        let mut mesh = mesh.clone();
+        mesh.indices = builder.indices;
+        mesh.index_count = mesh.indices.len() as i32;
+        ChunkedTerrain {
+            mesh,
+            chunks: builder.chunks,
+            root,
+            vao_id: 0,
+        }
+    }
+
+    /// Where the triangles of each chunk are in the indices of the mesh
+    pub fn chunk_ranges(&self) -> Vec<Range<usize>> {
+        self.chunks.iter().map(Chunk::indices).collect()
+    }
+
+    /// The triangles of the given chunk on their own, using only the vertices they need
+    pub fn chunk_mesh(&self, chunk: usize) -> Mesh {
+        self.mesh
+            .submesh(&self.mesh.indices[self.chunks[chunk].indices()])
+    }
+
+    /// Reorders the triangles of every chunk and the vertices for faster drawing, like
+    /// optimize::optimize_ranges. Each chunk is drawn on its own, so their triangles
+    /// are kept apart
+    pub fn optimize(&mut self) -> OptimizationReport {
+        let ranges = self.chunk_ranges();
+        let report = optimize::optimize_ranges(&mut self.mesh, &ranges);
+        // The triangles have moved, so they have to be sorted into the grids again
+        for chunk in &mut self.chunks {
+            chunk.grid = HeightGrid::new(&self.mesh, &self.mesh.indices[chunk.indices()]);
+        }
+        report
+    }
+
+    /// Makes the chunks within `load_distance` of the given point along the ground
+    /// resident, and evicts those further away than `evict_distance`. Chunks in between
+    /// stay as they are, so moving back and forth along an edge doesn't reload them
+    pub fn update_residency(
+        &mut self,
+        point: glm::Vec2,
+        load_distance: f32,
+        evict_distance: f32,
+    ) -> ResidencyChanges {
+        let mut changes = ResidencyChanges::default();
+        for (index, chunk) in self.chunks.iter_mut().enumerate() {
+            let distance = chunk.ground_distance(point);
+            if !chunk.resident && distance <= load_distance {
+                chunk.resident = true;
+                changes.loaded.push(index);
+            } else if chunk.resident && distance > evict_distance {
+                chunk.resident = false;
+                changes.evicted.push(index);
+            }
+        }
+        changes
+    }
+
+    /// Chunks which might be visible with the given view and projection transform,
+    /// skipping whole branches of the quadtree that are outside the view
+    pub fn visible_chunks(&self, view_projection: &glm::Mat4) -> Vec<usize> {
+        let planes = camera::frustum_planes(view_projection);
+        let mut visible = Vec::new();
+        let mut stack = vec![&self.root];
+        while let Some(node) = stack.pop() {
+            if !camera::box_in_frustum(&planes, &node.min, &node.max) {
+                continue;
+            }
+            if let Some(chunk) = node.chunk {
+                visible.push(chunk);
+            }
+            stack.extend(node.children.iter());
+        }
+        visible
+    }
+
+    /// Height of the terrain at the given point along the ground, or None if the point
+    /// is off the terrain. Where the terrain overlaps itself, the highest surface counts
+    pub fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        let mut height: Option<f32> = None;
+        let mut stack = vec![&self.root];
+        while let Some(node) = stack.pop() {
+            if x < node.min.x || x > node.max.x || z < node.min.z || z > node.max.z {
+                continue;
+            }
+            if let Some(y) = node
+                .chunk
+                .and_then(|chunk| self.chunks[chunk].height_at(&self.mesh, x, z))
+            {
+                height = Some(height.map_or(y, |h| h.max(y)));
+            }
+            stack.extend(node.children.iter());
+        }
+        height
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heightmap::{self, NoiseSettings};
+
+    #[test]
+    fn chunk_bounds() {
+        let mesh = heightmap::from_noise(33, 100.0, 10.0, &NoiseSettings::default());
+        let terrain = ChunkedTerrain::new(&mesh, 128);
+
+        let mut triangles = 0;
+        for (index, chunk) in terrain.chunks.iter().enumerate() {
+            let part = terrain.chunk_mesh(index);
+            assert_eq!(part.indices.len(), chunk.index_count as usize);
+            triangles += part.indices.len() / 3;
+
+            let (center, radius) = chunk.bounding_sphere();
+            for vertex in 0..part.vertex_count() {
+                let p = part.position(vertex);
+                assert!(
+                    p >= chunk.min && p <= chunk.max,
+                    "{:?} is outside chunk {}",
+                    p,
+                    index
+                );
+                assert!(glm::distance(&p, &center) <= radius * 1.0001);
+            }
+        }
+        assert_eq!(triangles, mesh.indices.len() / 3);
+    }
+
+    #[test]
+    fn residency() {
+        let mesh = heightmap::from_noise(33, 100.0, 10.0, &NoiseSettings::default());
+        let mut terrain = ChunkedTerrain::new(&mesh, 128);
+        let (min, max) = (terrain.root.min, terrain.root.max);
+        let corner = glm::vec2(min.x, min.z);
+        let far_corner = glm::vec2(max.x, max.z);
+        let far_chunk = (0..terrain.chunks.len())
+            .max_by(|&a, &b| {
+                let distance = |i: usize| terrain.chunks[i].ground_distance(corner);
+                distance(a).partial_cmp(&distance(b)).unwrap()
+            })
+            .unwrap();
+
+        // Standing in one corner leaves the chunks in the other one out
+        let changes = terrain.update_residency(corner, 30.0, 40.0);
+        assert!(!changes.loaded.is_empty());
+        assert!(changes.evicted.is_empty());
+        assert!(!terrain.chunks[far_chunk].resident);
+        for (index, chunk) in terrain.chunks.iter().enumerate() {
+            assert_eq!(chunk.resident, changes.loaded.contains(&index));
+            assert_eq!(chunk.resident, chunk.ground_distance(corner) <= 30.0);
+        }
+
+        // Loading everything, and then moving to the far corner and back evicts it again
+        terrain.update_residency(corner, f32::MAX, f32::MAX);
+        assert!(terrain.chunks.iter().all(|chunk| chunk.resident));
+        assert_eq!(
+            terrain.update_residency(far_corner, 30.0, 40.0).evicted,
+            (0..terrain.chunks.len())
+                .filter(|&i| terrain.chunks[i].ground_distance(far_corner) > 40.0)
+                .collect::<Vec<usize>>()
+        );
+        let changes = terrain.update_residency(corner, 30.0, 40.0);
+        assert!(changes.evicted.contains(&far_chunk));
+        assert!(!terrain.chunks[far_chunk].resident);
+    }
+
+    #[test]
+    fn height_at() {
+        let mesh = heightmap::from_noise(33, 100.0, 10.0, &NoiseSettings::default());
+        let mut terrain = ChunkedTerrain::new(&mesh, 128);
+        assert!(terrain.chunks.len() > 1);
+
+        // Every triangle of the mesh on its own, to check all of them at each point
+        let triangles: Vec<Chunk> = (0..mesh.indices.len() / 3)
+            .map(|t| Chunk {
+                first_index: t * 3,
+                index_count: 3,
+                min: glm::zero(),
+                max: glm::zero(),
+                lods: Vec::new(),
+                resident: false,
+                index_buffer_id: 0,
+                grid: HeightGrid::new(&mesh, &mesh.indices[t * 3..t * 3 + 3]),
+            })
+            .collect();
+
+        let (min, max) = (terrain.root.min, terrain.root.max);
+        for &optimized in &[false, true] {
+            if optimized {
+                terrain.optimize();
+            }
+            // Goes a little past the edges as well, where there is no terrain
+            for i in -1..=20 {
+                for j in -1..=20 {
+                    let x = min.x + (max.x - min.x) * i as f32 / 19.0;
+                    let z = min.z + (max.z - min.z) * j as f32 / 19.0;
+                    let expected = triangles
+                        .iter()
+                        .filter_map(|triangle| triangle.height_at(&mesh, x, z))
+                        .fold(None, |height: Option<f32>, y| {
+                            Some(height.map_or(y, |h| h.max(y)))
+                        });
+                    assert_eq!(terrain.height_at(x, z), expected, "at {}, {}", x, z);
+                }
+            }
+        }
+    }
+}
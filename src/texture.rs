@@ -0,0 +1,129 @@
+/// How texels are picked or blended when sampling a texture
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug)]
+pub enum FilterMode {
+    Nearest,
+    Linear,
+}
+
+/// What happens when sampling outside of the [0, 1] range
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug)]
+pub enum WrapMode {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+    ClampToBorder,
+}
+
+impl From<WrapMode> for gl::types::GLenum {
+    fn from(mode: WrapMode) -> Self {
+        match mode {
+            WrapMode::Repeat => gl::REPEAT,
+            WrapMode::MirroredRepeat => gl::MIRRORED_REPEAT,
+            WrapMode::ClampToEdge => gl::CLAMP_TO_EDGE,
+            WrapMode::ClampToBorder => gl::CLAMP_TO_BORDER,
+        }
+    }
+}
+
+/// A 2D RGBA texture living on the GPU. Like the scene graph, it is never freed:
+/// materials only hold on to the id, so the texture has to live as long as the program
+#[allow(dead_code)]
+pub struct Texture2D {
+    pub id: u32,
+    pub width: u32,
+    pub height: u32,
+    mipmapped: bool,
+}
+
+#[allow(dead_code)]
+impl Texture2D {
+    /// Loads an image file (PNG, JPEG, ...) into a new texture with mipmaps and
+    /// trilinear filtering
+    pub unsafe fn load(path: &str) -> Self {
+        println!("Loading texture {}...", path);
+        let image = image::open(path)
+            .unwrap_or_else(|e| panic!("Failed to load texture {}: {}", path, e))
+            // OpenGL expects the first row to be the bottom of the image
+            .flipv()
+            .into_rgba8();
+
+        Self::from_image(&image, true)
+    }
+
+    /// Uploads the given image to a new texture, optionally generating mipmaps for it
+    pub unsafe fn from_image(image: &image::RgbaImage, mipmaps: bool) -> Self {
+        let (width, height) = image.dimensions();
+
+        let mut id = 0u32;
+        gl::GenTextures(1, &mut id as *mut u32);
+        gl::BindTexture(gl::TEXTURE_2D, id);
+        gl::TexImage2D(
+            gl::TEXTURE_2D,
+            0,
+            gl::RGBA8 as i32,
+            width as i32,
+            height as i32,
+            0,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            image.as_raw().as_ptr() as *const std::ffi::c_void,
+        );
+
+        if mipmaps {
+            gl::GenerateMipmap(gl::TEXTURE_2D);
+        }
+
+        let texture = Texture2D {
+            id,
+            width,
+            height,
+            mipmapped: mipmaps,
+        };
+        texture.set_filtering(FilterMode::Linear, FilterMode::Linear);
+        texture.set_wrap(WrapMode::Repeat, WrapMode::Repeat);
+        texture
+    }
+
+    /// Sets the filtering used when the texture is shrunk (min) and magnified (mag).
+    /// Minification blends between mipmap levels as well, if there are any
+    pub unsafe fn set_filtering(&self, min: FilterMode, mag: FilterMode) {
+        let min_filter = match (min, self.mipmapped) {
+            (FilterMode::Nearest, false) => gl::NEAREST,
+            (FilterMode::Linear, false) => gl::LINEAR,
+            (FilterMode::Nearest, true) => gl::NEAREST_MIPMAP_NEAREST,
+            (FilterMode::Linear, true) => gl::LINEAR_MIPMAP_LINEAR,
+        };
+        let mag_filter = match mag {
+            FilterMode::Nearest => gl::NEAREST,
+            FilterMode::Linear => gl::LINEAR,
+        };
+
+        gl::BindTexture(gl::TEXTURE_2D, self.id);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, min_filter as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, mag_filter as i32);
+    }
+
+    /// Sets the wrap mode along the horizontal (s) and vertical (t) axis
+    pub unsafe fn set_wrap(&self, s: WrapMode, t: WrapMode) {
+        let s: gl::types::GLenum = s.into();
+        let t: gl::types::GLenum = t.into();
+
+        gl::BindTexture(gl::TEXTURE_2D, self.id);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, s as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, t as i32);
+    }
+
+    /// Binds this texture to the given texture unit
+    pub unsafe fn bind(&self, unit: u32) {
+        bind_texture(self.id, unit);
+    }
+}
+
+/// Binds the texture with the given id to the given texture unit. An id of 0
+/// unbinds whatever was bound to the unit
+pub unsafe fn bind_texture(texture_id: u32, unit: u32) {
+    gl::ActiveTexture(gl::TEXTURE0 + unit);
+    gl::BindTexture(gl::TEXTURE_2D, texture_id);
+}
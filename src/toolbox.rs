@@ -1,6 +1,21 @@
 extern crate nalgebra_glm as glm;
 use std::f64::consts::PI;
 
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+use crate::animation::{Animation, Interpolation, Repeat, Target, Track};
+
+// Time step used to estimate how fast a path turns
+const DERIVATIVE_STEP: f64 = 1e-3;
+// Seconds between the keyframes of a flight path turned into an animation
+const KEYFRAME_INTERVAL: f64 = 0.1;
+// Paths that take longer than this to repeat only have this much of them animated,
+// and jump back to the start afterwards
+const MAX_ANIMATION_DURATION: f64 = 120.0;
+// Frequencies closer together than this are treated as the same
+const FREQUENCY_TOLERANCE: f64 = 1e-6;
+
 pub struct Heading {
     pub x     : f32,
     pub z     : f32,
@@ -9,28 +24,265 @@ pub struct Heading {
     pub yaw   : f32,
 }
 
-pub fn simple_heading_animation(time: f32) -> Heading {
-    let t             = time as f64;
-    let step          = 0.05f64;
-    let path_size     = 15f64;
-    let circuit_speed = 0.8f64;
+/// A wave along one axis of a wandering path
+#[derive(Clone, Copy, Debug)]
+pub struct Wave {
+    pub amplitude : f64,
+    pub frequency : f64,
+    pub phase     : f64,
+}
 
-    let xpos      = path_size * (2.0 * (t+ 0.0) * circuit_speed).sin();
-    let xpos_next = path_size * (2.0 * (t+step) * circuit_speed).sin();
-    let zpos      = 3.0 * path_size * ((t+ 0.0) * circuit_speed).cos();
-    let zpos_next = 3.0 * path_size * ((t+step) * circuit_speed).cos();
+/// Shape of a flight path along the ground, in time units before the path speed
+#[derive(Clone, Debug)]
+pub enum FlightShape {
+    // x = width sin(frequency_x t + phase), z = length cos(frequency_z t)
+    Lissajous { width: f64, length: f64, frequency_x: f64, frequency_z: f64, phase: f64 },
+    Circle    { radius: f64 },
+    // Closed loop through the points, one point per time unit
+    Waypoints { points: Vec<glm::DVec2> },
+    // Sums of waves along x and z
+    Wander    { x: Vec<Wave>, z: Vec<Wave> },
+}
 
-    let delta_pos = glm::vec2(xpos_next - xpos, zpos_next - zpos);
+/// Where a helicopter flies, and how it leans while doing so
+#[derive(Clone, Debug)]
+pub struct FlightPath {
+    pub shape        : FlightShape,
+    pub center       : glm::DVec2,
+    pub speed        : f64, // How fast time passes along the path
+    pub pitch_factor : f64, // How far the nose dips per unit of speed
+    pub gravity      : f64, // Banks as much as a coordinated turn would with this gravity
+    pub max_roll     : f64,
+}
+
+impl Default for FlightPath {
+    /// The figure-eight the helicopters have always flown
+    fn default() -> Self {
+        FlightPath::new(FlightShape::Lissajous {
+            width       : 15.0,
+            length      : 45.0,
+            frequency_x : 2.0,
+            frequency_z : 1.0,
+            phase       : 0.0,
+        })
+    }
+}
 
-    let roll  = (t * circuit_speed).cos() * 0.5;
-    let pitch = -0.175 * glm::length(&delta_pos);
-    let yaw   = PI + delta_pos.x.atan2(delta_pos.y);
+impl FlightPath {
+    pub fn new(shape: FlightShape) -> FlightPath {
+        FlightPath {
+            shape,
+            center       : glm::vec2(0.0, 0.0),
+            speed        : 0.8,
+            pitch_factor : 0.00875,
+            gravity      : 40.0,
+            max_roll     : 0.6,
+        }
+    }
+
+    /// A path wandering around within `radius` of the center. The same seed always
+    /// gives the same path. Every wave is a whole multiple of one slow frequency, so
+    /// the path comes back around after a minute or so instead of never repeating
+    pub fn wander(seed: u64, radius: f64) -> FlightPath {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let base = rng.gen_range(0.1..0.15);
+        let mut waves = |count: usize| -> Vec<Wave> {
+            (0..count)
+                .map(|i| Wave {
+                    // Slower waves sweep out the area, faster ones add some wiggle
+                    amplitude : radius / (i + 2) as f64,
+                    frequency : base * (rng.gen_range(3..=5) * (i + 1)) as f64,
+                    phase     : rng.gen_range(0.0..2.0 * PI),
+                })
+                .collect()
+        };
+        let (x, z) = (waves(3), waves(3));
+        FlightPath::new(FlightShape::Wander { x, z })
+    }
+
+    /// Position along the ground at the given time
+    pub fn position(&self, time: f64) -> glm::DVec2 {
+        let t = time * self.speed;
+        let offset = match &self.shape {
+            FlightShape::Lissajous { width, length, frequency_x, frequency_z, phase } => glm::vec2(
+                width * (frequency_x * t + phase).sin(),
+                length * (frequency_z * t).cos(),
+            ),
+            FlightShape::Circle { radius } => glm::vec2(radius * t.sin(), radius * t.cos()),
+            FlightShape::Waypoints { points } if points.is_empty() => glm::vec2(0.0, 0.0),
+            FlightShape::Waypoints { points } => {
+                // Uniform Catmull-Rom, wrapping around to the first point
+                let n = points.len() as i64;
+                let i = t.floor() as i64;
+                let u = t - t.floor();
+                let point = |k: i64| points[k.rem_euclid(n) as usize];
+                let (a, b, c, d) = (point(i - 1), point(i), point(i + 1), point(i + 2));
+                (b * 2.0
+                    + (c - a) * u
+                    + (a * 2.0 - b * 5.0 + c * 4.0 - d) * (u * u)
+                    + (b * 3.0 - a - c * 3.0 + d) * (u * u * u))
+                    * 0.5
+            }
+            FlightShape::Wander { x, z } => {
+                let sum = |waves: &Vec<Wave>| {
+                    waves
+                        .iter()
+                        .map(|w| w.amplitude * (w.frequency * t + w.phase).sin())
+                        .sum::<f64>()
+                };
+                glm::vec2(sum(x), sum(z))
+            }
+        };
+        self.center + offset
+    }
+
+    /// Roll, pitch and yaw for moving along the ground (x and z) with the given velocity
+    /// and acceleration. Faces along the motion, dips the nose with speed and banks into
+    /// turns
+    pub fn lean(&self, velocity: glm::DVec2, acceleration: glm::DVec2) -> (f64, f64, f64) {
+        let speed = glm::length(&velocity);
+
+        // Sideways acceleration, positive when turning left. A coordinated turn banks
+        // just enough for the lift to cancel it out along with gravity
+        let sideways = if speed > 0.0 {
+            (acceleration.x * velocity.y - acceleration.y * velocity.x) / speed
+        } else {
+            0.0
+        };
+        let roll  = (sideways / self.gravity).atan().max(-self.max_roll).min(self.max_roll);
+        let pitch = -self.pitch_factor * speed;
+        let yaw   = PI + velocity.x.atan2(velocity.y);
+        (roll, pitch, yaw)
+    }
+
+    /// Seconds it takes to get back to the start, or a very long time if the path
+    /// never quite repeats
+    pub fn period(&self) -> f64 {
+        let frequency = match &self.shape {
+            FlightShape::Lissajous { frequency_x, frequency_z, .. } => {
+                common_frequency([*frequency_x, *frequency_z].iter().copied())
+            }
+            FlightShape::Circle { .. } => 1.0,
+            FlightShape::Waypoints { points } => 2.0 * PI / points.len() as f64,
+            FlightShape::Wander { x, z } => {
+                common_frequency(x.iter().chain(z.iter()).map(|w| w.frequency))
+            }
+        };
+        2.0 * PI / (frequency * self.speed)
+    }
+
+    /// Keyframes following the path at the given altitude, looping once the path
+    /// repeats. Played with a time offset, several helicopters can share a path
+    /// without running into each other
+    pub fn animation(&self, altitude: f32) -> Animation {
+        let duration = self.period().min(MAX_ANIMATION_DURATION);
+        let steps = (duration / KEYFRAME_INTERVAL).ceil().max(1.0) as usize;
+
+        let mut position = Track::new(Target::Position, Interpolation::Cubic);
+        let mut rotation = Track::new(Target::Rotation, Interpolation::Cubic);
+        let mut previous_yaw: Option<f32> = None;
+        for step in 0..=steps {
+            let time = duration * step as f64 / steps as f64;
+            let heading = self.heading(time);
+            // Keep turning the same way where the yaw wraps around, instead of
+            // spinning a whole turn back between two keyframes
+            let yaw = match previous_yaw {
+                Some(previous) => {
+                    let turn = 2.0 * std::f32::consts::PI;
+                    previous + (heading.yaw - previous + turn / 2.0).rem_euclid(turn) - turn / 2.0
+                }
+                None => heading.yaw,
+            };
+            previous_yaw = Some(yaw);
+            position = position.key(time as f32, glm::vec3(heading.x, altitude, heading.z));
+            rotation = rotation.key(time as f32, glm::vec3(heading.pitch, yaw, heading.roll));
+        }
+
+        Animation::new(Repeat::Loop)
+            .with_track(position)
+            .with_track(rotation)
+    }
+
+    /// Where the path is at the given time, and how to lean along it
+    pub fn heading(&self, time: f64) -> Heading {
+        let h = DERIVATIVE_STEP;
+        let position = self.position(time);
+        let (before, after) = (self.position(time - h), self.position(time + h));
+        let velocity     = (after - before) / (2.0 * h);
+        let acceleration = (after - position * 2.0 + before) / (h * h);
+        let (roll, pitch, yaw) = self.lean(velocity, acceleration);
+
+        Heading {
+            x     : position.x as f32,
+            z     : position.y as f32,
+            roll  : roll       as f32,
+            pitch : pitch      as f32,
+            yaw   : yaw        as f32,
+        }
+    }
+}
+
+/// Largest frequency that all of the given ones are whole multiples of, so waves with
+/// these frequencies all line up again after one period of it
+fn common_frequency(frequencies: impl Iterator<Item = f64>) -> f64 {
+    frequencies.fold(0.0, |a, b| {
+        let (mut a, mut b) = (a.abs(), b.abs());
+        while b > FREQUENCY_TOLERANCE {
+            let remainder = a % b;
+            a = b;
+            b = remainder;
+        }
+        a
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_repeat() {
+        let waypoints = vec![
+            glm::vec2(-40.0, -30.0),
+            glm::vec2(35.0, -25.0),
+            glm::vec2(-25.0, 30.0),
+        ];
+        let shapes = vec![
+            FlightShape::Lissajous {
+                width: 20.0,
+                length: 10.0,
+                frequency_x: 3.0,
+                frequency_z: 2.0,
+                phase: 0.5,
+            },
+            FlightShape::Circle { radius: 35.0 },
+            FlightShape::Waypoints { points: waypoints },
+        ];
+        let paths = shapes
+            .into_iter()
+            .map(FlightPath::new)
+            .chain((0..5).map(|seed| FlightPath::wander(seed, 35.0)));
+
+        for path in paths {
+            let period = path.period();
+            assert!(period < MAX_ANIMATION_DURATION, "{:?} takes {}s", path.shape, period);
+            for i in 0..20 {
+                let time = i as f64 * 0.37;
+                let distance = glm::distance(&path.position(time), &path.position(time + period));
+                assert!(distance < 1e-6, "{:?} is {} off at {}", path.shape, distance, time);
+            }
+        }
+    }
 
-    Heading {
-        x     : xpos  as f32,
-        z     : zpos  as f32,
-        roll  : roll  as f32,
-        pitch : pitch as f32,
-        yaw   : yaw   as f32,
+    #[test]
+    fn default_figure_eight() {
+        let path = FlightPath::default();
+        assert!((path.period() - 2.0 * PI / 0.8).abs() < 1e-9);
+        for i in 0..50 {
+            let time = i as f64 * 0.2;
+            let x = 15.0 * (2.0 * time * 0.8).sin();
+            let z = 45.0 * (time * 0.8).cos();
+            assert!(glm::distance(&path.position(time), &glm::vec2(x, z)) < 1e-9);
+        }
     }
 }